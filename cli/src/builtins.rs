@@ -0,0 +1,390 @@
+//! Opt-in standard builtins. None of them are part of a Shell until they are
+//! enabled one by one while building it:
+//!
+//! ```
+//! use cli::builtins::Builtin;
+//! use cli::shell::{CommandSet, Shell};
+//!
+//! let shell = Shell::new(CommandSet::new(), "My tool")
+//!     .with_builtin(Builtin::Help)
+//!     .with_builtin(Builtin::Exit);
+//! ```
+use crate::command::flag::{self, ArgSpec, FlagQuery, FlagSpec, FlagSpecSet};
+use crate::command::operand::{MissingOperandError, Operand};
+use crate::command::output::{Output, Table};
+use crate::command::{self, Command, ReturnCode};
+use crate::shell::{session, Context, Shell};
+use crate::{shell_print, shell_println};
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::thread;
+use std::time::Instant;
+
+/// nesting limit for `source`, so that a file sourcing itself becomes an
+/// error instead of a stack overflow
+const MAX_SOURCE_DEPTH: usize = 64;
+
+thread_local! {
+    static SOURCE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+#[derive(Debug)]
+pub struct UnsetVariableError(pub String);
+
+impl fmt::Display for UnsetVariableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error: variable {} is not set", self.0)
+    }
+}
+
+impl Error for UnsetVariableError {}
+
+#[derive(Debug)]
+pub struct SourceDepthError(pub String);
+
+impl fmt::Display for SourceDepthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Error: source {} nested deeper than {} files",
+            self.0, MAX_SOURCE_DEPTH
+        )
+    }
+}
+
+impl Error for SourceDepthError {}
+
+/// The standard builtins
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Builtin {
+    /// `help`, list the commands of the shell
+    Help,
+    /// `exit`, leave the shell
+    Exit,
+    /// `echo [-n] words...`, print the operands
+    Echo,
+    /// `set NAME value...`, store a context variable
+    Set,
+    /// `get NAME`, print a context variable
+    Get,
+    /// `sleep DURATION`, e.g. `sleep 1.5s`
+    Sleep,
+    /// `time command...`, run a command and print how long it took
+    Time,
+    /// `repeat N command...`, run a command N times
+    Repeat,
+    /// `clear`, clear the terminal
+    Clear,
+    /// `history [N]`, list the (last N) command lines of this session
+    History,
+    /// `source FILE`, run the statements in a file
+    Source,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 11] = [
+        Builtin::Help,
+        Builtin::Exit,
+        Builtin::Echo,
+        Builtin::Set,
+        Builtin::Get,
+        Builtin::Sleep,
+        Builtin::Time,
+        Builtin::Repeat,
+        Builtin::Clear,
+        Builtin::History,
+        Builtin::Source,
+    ];
+
+    /// the command config implementing this builtin
+    pub fn config(&self) -> command::Config {
+        match self {
+            Builtin::Help => help(),
+            Builtin::Exit => exit(),
+            Builtin::Echo => echo(),
+            Builtin::Set => set(),
+            Builtin::Get => get(),
+            Builtin::Sleep => sleep(),
+            Builtin::Time => time(),
+            Builtin::Repeat => repeat(),
+            Builtin::Clear => clear(),
+            Builtin::History => history(),
+            Builtin::Source => source(),
+        }
+    }
+}
+
+impl Shell {
+    /// Add a standard builtin to the shell. It replaces a command with the
+    /// same name.
+    ///
+    /// Panics if the builtin collides with a command under match options
+    /// already set; add builtins before calling with_matching, which reports
+    /// collisions as an error.
+    pub fn with_builtin(self, builtin: Builtin) -> Shell {
+        if let Err(error) = self.register_command(builtin.config()) {
+            panic!("{}", error);
+        }
+        self
+    }
+}
+
+/// fail unless there are at least `expected` operands
+fn require_operands(command: &Command, expected: usize) -> Result<(), MissingOperandError> {
+    if command.operands().len() < expected {
+        return Err(MissingOperandError(command.operands().clone(), expected));
+    }
+    Ok(())
+}
+
+fn values(operands: &[Operand]) -> Vec<&str> {
+    operands.iter().map(|o| o.value()).collect()
+}
+
+fn owned_values(operands: &[Operand]) -> Vec<String> {
+    operands.iter().map(|o| o.value().to_string()).collect()
+}
+
+pub fn help() -> command::Config {
+    command::Config::new(
+        "help",
+        FlagSpecSet::new(),
+        "Print this help message",
+        |_command: &Command,
+         shell: &Shell,
+         _context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            shell_println!("{}", shell.help());
+            Ok(ReturnCode::Ok)
+        },
+    )
+}
+
+pub fn exit() -> command::Config {
+    command::Config::new(
+        "exit",
+        FlagSpecSet::new(),
+        "Quit the command line interface.",
+        |_command: &Command,
+         _shell: &Shell,
+         _context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> { Ok(ReturnCode::Abort) },
+    )
+}
+
+pub fn echo() -> command::Config {
+    let mut flags = FlagSpecSet::new();
+    flags.insert(FlagSpec::new(
+        "no-newline",
+        'n',
+        ArgSpec::default(),
+        "Do not end the output with a newline",
+    ));
+
+    command::Config::new(
+        "echo",
+        flags,
+        "Print the operands separated by spaces",
+        |command: &Command,
+         _shell: &Shell,
+         _context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            let text = values(command.operands()).join(" ");
+            match flag::query_flag(&FlagQuery::Name("no-newline".into()), command.flags()) {
+                Some(_) => shell_print!("{}", text),
+                None => shell_println!("{}", text),
+            }
+            Ok(ReturnCode::Ok)
+        },
+    )
+    .passthrough()
+}
+
+pub fn set() -> command::Config {
+    command::Config::new(
+        "set",
+        FlagSpecSet::new(),
+        "Store the remaining operands in a context variable, e.g. set name some value",
+        |command: &Command,
+         _shell: &Shell,
+         context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            require_operands(command, 1)?;
+            let operands = command.operands();
+            context.insert(operands[0].value().into(), values(&operands[1..]).join(" "));
+            Ok(ReturnCode::Ok)
+        },
+    )
+    .passthrough()
+}
+
+pub fn get() -> command::Config {
+    command::Config::new(
+        "get",
+        FlagSpecSet::new(),
+        "Print the value of a context variable",
+        |command: &Command,
+         _shell: &Shell,
+         context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            require_operands(command, 1)?;
+            let name = command.operands()[0].value();
+            match context.get(name) {
+                Some(value) => shell_println!("{}", value),
+                None => return Err(Box::new(UnsetVariableError(name.into()))),
+            }
+            Ok(ReturnCode::Ok)
+        },
+    )
+}
+
+pub fn sleep() -> command::Config {
+    command::Config::new(
+        "sleep",
+        FlagSpecSet::new(),
+        "Wait for a while, e.g. sleep 500ms",
+        |command: &Command,
+         _shell: &Shell,
+         _context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            require_operands(command, 1)?;
+            thread::sleep(command.operands()[0].as_duration()?);
+            Ok(ReturnCode::Ok)
+        },
+    )
+}
+
+pub fn time() -> command::Config {
+    command::Config::new(
+        "time",
+        FlagSpecSet::new(),
+        "Run a command and print how long it took, e.g. time calc 2 ** 10",
+        |command: &Command,
+         shell: &Shell,
+         context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            require_operands(command, 1)?;
+            let start = Instant::now();
+            let code = shell.run_command(owned_values(command.operands()), context);
+            shell_println!("time: {:.3}s", start.elapsed().as_secs_f64());
+            Ok(code)
+        },
+    )
+    .passthrough()
+    .no_glob()
+}
+
+pub fn repeat() -> command::Config {
+    command::Config::new(
+        "repeat",
+        FlagSpecSet::new(),
+        "Run a command a number of times, e.g. repeat 3 echo hi",
+        |command: &Command,
+         shell: &Shell,
+         context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            require_operands(command, 2)?;
+            let count = command.operands()[0].value_as::<usize>()?;
+            let args = owned_values(&command.operands()[1..]);
+
+            let mut code = ReturnCode::Ok;
+            for _ in 0..count {
+                code = shell.run_command(args.clone(), context);
+                if code == ReturnCode::Abort {
+                    break;
+                }
+            }
+            Ok(code)
+        },
+    )
+    .passthrough()
+    .no_glob()
+}
+
+pub fn clear() -> command::Config {
+    command::Config::new(
+        "clear",
+        FlagSpecSet::new(),
+        "Clear the terminal",
+        |_command: &Command,
+         _shell: &Shell,
+         _context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            // erase the screen and move the cursor to the top left corner
+            shell_print!("\x1b[2J\x1b[H");
+            Ok(ReturnCode::Ok)
+        },
+    )
+}
+
+pub fn history() -> command::Config {
+    command::Config::new(
+        "history",
+        FlagSpecSet::new(),
+        "List the command lines entered in this session, or the last N",
+        |command: &Command,
+         _shell: &Shell,
+         _context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            let history = session::history();
+            let skip = match command.operands().first() {
+                Some(count) => history.len().saturating_sub(count.value_as::<usize>()?),
+                None => 0,
+            };
+
+            let mut table = Table::new(&["#", "command"]);
+            for (idx, line) in history.iter().enumerate().skip(skip) {
+                table.push_row(vec![(idx as i64 + 1).into(), line.as_str().into()]);
+            }
+            Ok(ReturnCode::Data(Output::Table(table)))
+        },
+    )
+}
+
+pub fn source() -> command::Config {
+    command::Config::new(
+        "source",
+        FlagSpecSet::new(),
+        "Run the statements in a file",
+        |command: &Command,
+         shell: &Shell,
+         context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            require_operands(command, 1)?;
+            let path = command.operands()[0].as_file()?;
+            let depth = SOURCE_DEPTH.with(|d| d.get());
+            if depth >= MAX_SOURCE_DEPTH {
+                return Err(Box::new(SourceDepthError(path.display().to_string())));
+            }
+
+            let text = fs::read_to_string(path)?;
+            SOURCE_DEPTH.with(|d| d.set(depth + 1));
+            let result = shell.execute(&text, context);
+            SOURCE_DEPTH.with(|d| d.set(depth));
+            result
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::CommandSet;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn source_nesting() {
+        let path = env::temp_dir().join(format!("cli-source-{}.cli", process::id()));
+        fs::write(&path, format!("source {}\n", path.display())).unwrap();
+        let shell = Shell::new(CommandSet::new(), "").with_builtin(Builtin::Source);
+
+        let mut context = Context::new();
+        let code = shell.run_args(&["source", path.to_str().unwrap()], &mut context);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(ReturnCode::Failure(1), code.unwrap());
+        assert_eq!(0, SOURCE_DEPTH.with(|d| d.get()));
+    }
+}
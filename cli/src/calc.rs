@@ -0,0 +1,486 @@
+//! Arithmetic expression evaluator backing the `calc` command.
+//!
+//! Supported syntax, from lowest to highest precedence:
+//!
+//! | operators      | meaning                                  |
+//! |----------------|------------------------------------------|
+//! | `\|`           | bitwise or                               |
+//! | `^`            | bitwise xor                              |
+//! | `&`            | bitwise and                              |
+//! | `<<` `>>`      | shifts                                   |
+//! | `+` `-`        | addition, subtraction                    |
+//! | `*` `/` `%`    | multiplication, division, remainder      |
+//! | `-` `+` `~`    | unary negation, plus, bitwise not        |
+//! | `**`           | power (right associative)                |
+//!
+//! Operands are integer literals (`42`, `0xff`, `0b101`), float literals
+//! (`1.5`, `2e10`), `$name` references into the shell Context and
+//! parenthesized sub-expressions. Integer arithmetic is done on i64 and
+//! stays integer (so `7 / 2` is `3`); as soon as one side is a float the
+//! operation is done in f64.
+use crate::shell::Context;
+use std::error::Error;
+use std::fmt;
+
+/// Result of evaluating an expression
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+}
+
+impl Value {
+    /// parse a plain numeric literal, e.g. a value read back from Context
+    pub fn parse(text: &str) -> Option<Value> {
+        let text = text.trim();
+        if looks_like_int(text) || is_radix_literal(text) {
+            // an integer literal that does not fit stays an error rather
+            // than silently losing precision as a float
+            return parse_int(text).map(Value::Int);
+        }
+        text.parse::<f64>()
+            .ok()
+            .filter(|f| f.is_finite())
+            .map(Value::Float)
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Value::Int(i) => i as f64,
+            Value::Float(f) => f,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            // Debug keeps the trailing ".0" so the value reads back as a float
+            Value::Float(x) => write!(f, "{:?}", x),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CalcError {
+    /// malformed expression; the position is a 1-based character column
+    Syntax(usize, String),
+    UnknownVariable(String),
+    /// a referenced Context value does not hold a number
+    InvalidVariable(String, String),
+    Overflow,
+    DivideByZero,
+    /// float operation without a defined result, e.g. a root of a negative
+    Undefined(String),
+    /// operator that is only defined for integers was given a float
+    IntegerRequired(&'static str),
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalcError::Syntax(pos, msg) => write!(f, "syntax error at column {}: {}", pos, msg),
+            CalcError::UnknownVariable(name) => write!(f, "unknown variable '${}'", name),
+            CalcError::InvalidVariable(name, value) => {
+                write!(f, "variable '${}' is not a number: '{}'", name, value)
+            }
+            CalcError::Overflow => write!(f, "arithmetic overflow"),
+            CalcError::DivideByZero => write!(f, "division by zero"),
+            CalcError::Undefined(what) => write!(f, "{} is undefined", what),
+            CalcError::IntegerRequired(op) => {
+                write!(f, "operator '{}' requires integer operands", op)
+            }
+        }
+    }
+}
+
+impl Error for CalcError {}
+
+/// Evaluate an expression, resolving `$name` references against context.
+pub fn evaluate(expression: &str, context: &Context) -> Result<Value, CalcError> {
+    let tokens = tokenize(expression)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        context,
+    };
+    let value = parser.expr()?;
+
+    match parser.peek() {
+        None => Ok(value),
+        Some((col, token)) => Err(CalcError::Syntax(*col, format!("unexpected '{}'", token))),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(Value),
+    Variable(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(v) => write!(f, "{}", v),
+            Token::Variable(name) => write!(f, "${}", name),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+        }
+    }
+}
+
+/// operators, longest first so "**" wins over "*"
+const OPERATORS: [&str; 12] = [
+    "**", "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~",
+];
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, CalcError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let c = chars[idx];
+        let col = idx + 1;
+
+        if c.is_whitespace() {
+            idx += 1;
+        } else if c == '(' {
+            tokens.push((col, Token::LParen));
+            idx += 1;
+        } else if c == ')' {
+            tokens.push((col, Token::RParen));
+            idx += 1;
+        } else if c == '$' {
+            let start = idx + 1;
+            idx = start;
+            while idx < chars.len() && is_variable_char(chars[idx]) {
+                idx += 1;
+            }
+            if idx == start {
+                return Err(CalcError::Syntax(
+                    col,
+                    "expected variable name after '$'".into(),
+                ));
+            }
+            tokens.push((col, Token::Variable(chars[start..idx].iter().collect())));
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = idx;
+            while idx < chars.len() {
+                let d = chars[idx];
+                let exponent_sign = (d == '+' || d == '-')
+                    && matches!(chars[idx - 1], 'e' | 'E')
+                    && !is_radix_literal(&chars[start..idx].iter().collect::<String>());
+                if d.is_ascii_alphanumeric() || d == '.' || d == '_' || exponent_sign {
+                    idx += 1;
+                } else {
+                    break;
+                }
+            }
+            let literal: String = chars[start..idx].iter().filter(|d| **d != '_').collect();
+            match Value::parse(&literal) {
+                Some(v) => tokens.push((col, Token::Number(v))),
+                None if looks_like_int(&literal) || is_radix_literal(&literal) => {
+                    return Err(CalcError::Overflow)
+                }
+                None => {
+                    return Err(CalcError::Syntax(
+                        col,
+                        format!("invalid number '{}'", literal),
+                    ))
+                }
+            }
+        } else {
+            let rest: String = chars[idx..].iter().take(2).collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    tokens.push((col, Token::Op(op)));
+                    idx += op.len();
+                }
+                _ => {
+                    return Err(CalcError::Syntax(
+                        col,
+                        format!("unexpected character '{}'", c),
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn is_variable_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+fn is_radix_literal(text: &str) -> bool {
+    matches!(text.get(..2), Some("0x" | "0X" | "0b" | "0B" | "0o" | "0O"))
+}
+
+fn looks_like_int(text: &str) -> bool {
+    let unsigned = text.strip_prefix('-').unwrap_or(text);
+    !unsigned.is_empty() && unsigned.chars().all(|c| c.is_ascii_digit())
+}
+
+fn parse_int(text: &str) -> Option<i64> {
+    let (digits, radix) = match text.get(..2) {
+        Some("0x") | Some("0X") => (&text[2..], 16),
+        Some("0b") | Some("0B") => (&text[2..], 2),
+        Some("0o") | Some("0O") => (&text[2..], 8),
+        _ => (text, 10),
+    };
+    let unsigned = digits.strip_prefix('-').unwrap_or(digits);
+    if unsigned.is_empty() || !unsigned.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// recursive descent parser that evaluates as it goes
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    context: &'a Context,
+}
+
+/// binary operator precedence levels, loosest binding first
+const BINARY_LEVELS: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn end_column(&self) -> usize {
+        self.tokens
+            .last()
+            .map(|(col, t)| col + t.to_string().len())
+            .unwrap_or(1)
+    }
+
+    fn next_op_in(&self, ops: &[&str]) -> Option<&'static str> {
+        match self.peek() {
+            Some((_, Token::Op(op))) if ops.contains(op) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self) -> Result<Value, CalcError> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Value, CalcError> {
+        if level == BINARY_LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self.next_op_in(BINARY_LEVELS[level]) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = apply_binary(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Value, CalcError> {
+        match self.next_op_in(&["-", "+", "~"]) {
+            Some(op) => {
+                self.pos += 1;
+                let operand = self.unary()?;
+                apply_unary(op, operand)
+            }
+            None => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<Value, CalcError> {
+        let base = self.primary()?;
+        if self.next_op_in(&["**"]).is_some() {
+            self.pos += 1;
+            // right associative, and the exponent may carry its own sign
+            let exponent = self.unary()?;
+            return apply_binary("**", base, exponent);
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Value, CalcError> {
+        let (col, token) = match self.tokens.get(self.pos) {
+            Some(t) => t.clone(),
+            None => {
+                return Err(CalcError::Syntax(
+                    self.end_column(),
+                    "unexpected end of expression".into(),
+                ))
+            }
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Number(v) => Ok(v),
+            Token::Variable(name) => {
+                let raw = self
+                    .context
+                    .get(&name)
+                    .ok_or_else(|| CalcError::UnknownVariable(name.clone()))?;
+                Value::parse(raw).ok_or_else(|| CalcError::InvalidVariable(name, raw.clone()))
+            }
+            Token::LParen => {
+                let value = self.expr()?;
+                match self.peek() {
+                    Some((_, Token::RParen)) => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    Some((c, t)) => Err(CalcError::Syntax(
+                        *c,
+                        format!("expected ')' but found '{}'", t),
+                    )),
+                    None => Err(CalcError::Syntax(col, "unclosed '('".into())),
+                }
+            }
+            t => Err(CalcError::Syntax(col, format!("unexpected '{}'", t))),
+        }
+    }
+}
+
+fn apply_unary(op: &'static str, v: Value) -> Result<Value, CalcError> {
+    match (op, v) {
+        ("+", v) => Ok(v),
+        ("-", Value::Int(i)) => i.checked_neg().map(Value::Int).ok_or(CalcError::Overflow),
+        ("-", Value::Float(f)) => Ok(Value::Float(-f)),
+        ("~", Value::Int(i)) => Ok(Value::Int(!i)),
+        (op, _) => Err(CalcError::IntegerRequired(op)),
+    }
+}
+
+fn apply_binary(op: &'static str, lhs: Value, rhs: Value) -> Result<Value, CalcError> {
+    if let (Value::Int(a), Value::Int(b)) = (lhs, rhs) {
+        return integer_op(op, a, b);
+    }
+
+    let (a, b) = (lhs.as_f64(), rhs.as_f64());
+    let result = match op {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" | "%" if b == 0.0 => return Err(CalcError::DivideByZero),
+        "/" => a / b,
+        "%" => a % b,
+        "**" => a.powf(b),
+        op => return Err(CalcError::IntegerRequired(op)),
+    };
+
+    if result.is_infinite() {
+        Err(CalcError::Overflow)
+    } else if result.is_nan() {
+        Err(CalcError::Undefined(format!("{} {} {}", lhs, op, rhs)))
+    } else {
+        Ok(Value::Float(result))
+    }
+}
+
+fn integer_op(op: &'static str, a: i64, b: i64) -> Result<Value, CalcError> {
+    if (op == "/" || op == "%") && b == 0 {
+        return Err(CalcError::DivideByZero);
+    }
+    if op == "**" && b < 0 {
+        return apply_binary(op, Value::Float(a as f64), Value::Int(b));
+    }
+
+    let shift = |b: i64| u32::try_from(b).ok().filter(|b| *b < i64::BITS);
+    let result = match op {
+        "+" => a.checked_add(b),
+        "-" => a.checked_sub(b),
+        "*" => a.checked_mul(b),
+        "/" => a.checked_div(b),
+        "%" => a.checked_rem(b),
+        "**" => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+        "<<" => shift(b).and_then(|b| a.checked_shl(b)),
+        ">>" => shift(b).and_then(|b| a.checked_shr(b)),
+        "&" => Some(a & b),
+        "|" => Some(a | b),
+        "^" => Some(a ^ b),
+        _ => unreachable!("unhandled operator {}", op),
+    };
+
+    result.map(Value::Int).ok_or(CalcError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> Result<Value, CalcError> {
+        evaluate(expression, &Context::new())
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(Ok(Value::Int(14)), eval("2 + 3 * 4"));
+        assert_eq!(Ok(Value::Int(20)), eval("(2 + 3) * 4"));
+        assert_eq!(Ok(Value::Int(-4)), eval("-2 ** 2"));
+        assert_eq!(Ok(Value::Int(512)), eval("2 ** 3 ** 2"));
+        assert_eq!(Ok(Value::Int(3)), eval("1 | 2 ^ 3 & 6 << 1"));
+    }
+
+    #[test]
+    fn integer_and_float() {
+        assert_eq!(Ok(Value::Int(3)), eval("7 / 2"));
+        assert_eq!(Ok(Value::Float(3.5)), eval("7.0 / 2"));
+        assert_eq!(Ok(Value::Int(1)), eval("7 % 3"));
+        assert_eq!(Ok(Value::Float(0.5)), eval("2 ** -1"));
+        assert_eq!(Ok(Value::Int(255)), eval("0xf0 | 0b1111"));
+        assert_eq!("3.0", eval("1.5 * 2").unwrap().to_string());
+    }
+
+    #[test]
+    fn variables() {
+        let mut context = Context::new();
+        context.insert("x".into(), "4".into());
+        context.insert("name".into(), "bob".into());
+
+        assert_eq!(Ok(Value::Int(16)), evaluate("$x * $x", &context));
+        assert_eq!(
+            Err(CalcError::UnknownVariable("y".into())),
+            evaluate("$y + 1", &context)
+        );
+        assert_eq!(
+            Err(CalcError::InvalidVariable("name".into(), "bob".into())),
+            evaluate("$name", &context)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Err(CalcError::DivideByZero), eval("1 / 0"));
+        assert_eq!(Err(CalcError::DivideByZero), eval("1.5 % 0"));
+        assert_eq!(Err(CalcError::Overflow), eval("9223372036854775807 + 1"));
+        assert_eq!(Err(CalcError::Overflow), eval("2 ** 64"));
+        assert_eq!(Err(CalcError::Overflow), eval("1 << 64"));
+        assert_eq!(Err(CalcError::Overflow), eval("99999999999999999999"));
+        assert!(matches!(
+            eval("(0 - 8.0) ** 0.5"),
+            Err(CalcError::Undefined(_))
+        ));
+        assert_eq!(Err(CalcError::IntegerRequired("&")), eval("1.5 & 1"));
+        assert!(matches!(eval("(1 + 2"), Err(CalcError::Syntax(1, _))));
+        assert!(matches!(eval("1 +"), Err(CalcError::Syntax(4, _))));
+        assert!(matches!(eval("1 2"), Err(CalcError::Syntax(3, _))));
+        assert!(matches!(eval("1 ! 2"), Err(CalcError::Syntax(3, _))));
+    }
+}
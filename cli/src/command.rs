@@ -7,6 +7,7 @@ use std::error::Error;
 
 pub mod flag;
 pub mod operand;
+pub mod output;
 
 mod config;
 
@@ -23,11 +24,11 @@ impl<'a> Command<'a> {
     }
 
     pub fn execute(&self, shell: &Shell, context: &mut Context) -> Result<ReturnCode, Box<dyn Error>> {
-        (self.config.callback())(&self, shell, context)
+        (self.config.callback())(self, shell, context)
     }
     
     pub fn config(&self) -> &Config {
-        &self.config
+        self.config
     }
 
     pub fn flags(&self) -> &FlagSet<'a> {
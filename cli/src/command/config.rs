@@ -3,26 +3,81 @@ use std::error::Error;
 use std::fmt;
 use super::Command;
 use super::flag::FlagSpecSet;
+use super::output::Output;
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum ReturnCode {
     Ok,
     Abort,
+    /// the command ran but reported failure with the given exit status,
+    /// e.g. an external command exiting non-zero
+    Failure(i32),
+    /// the command succeeded and produced structured data for the shell to
+    /// render in the selected output format
+    Data(Output),
+}
+
+impl ReturnCode {
+    /// numeric exit status, following the usual convention of 0 for success
+    pub fn status(&self) -> i32 {
+        match self {
+            ReturnCode::Ok | ReturnCode::Abort | ReturnCode::Data(_) => 0,
+            ReturnCode::Failure(code) => *code,
+        }
+    }
 }
 
 pub type Callback = fn(&Command, shell: &Shell, &mut shell::Context) -> Result<ReturnCode, Box<dyn Error>>;
 
 /// All specifications to run a Command. Each flag must be unique, according to
 /// PartialEq defined on flag::FlagId.
+#[derive(Clone)]
 pub struct Config {
     name: String,
     flags: FlagSpecSet,
     help: String,
     callback: Callback,
+    hidden: bool,
+    glob: bool,
+    passthrough: bool,
 }
 
 impl Config {
     pub fn new(name: &str, flags: FlagSpecSet, help: &str, callback: Callback) -> Config {
-        Config { name: name.into(), flags, help: help.into(), callback }
+        Config { name: name.into(), flags, help: help.into(), callback, hidden: false, glob: true, passthrough: false }
+    }
+
+    /// Keep this command out of the shell help and generated documentation.
+    /// It can still be run by name.
+    pub fn hidden(mut self) -> Config {
+        self.hidden = true;
+        self
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    /// Pass unquoted wildcards in operands through as they are instead of
+    /// expanding them to file names, e.g. for commands taking expressions.
+    pub fn no_glob(mut self) -> Config {
+        self.glob = false;
+        self
+    }
+
+    pub fn globs_operands(&self) -> bool {
+        self.glob
+    }
+
+    /// Treat everything from the first operand on as operands, flags
+    /// included, e.g. for commands that run the command line they are given.
+    pub fn passthrough(mut self) -> Config {
+        self.passthrough = true;
+        self
+    }
+
+    pub fn is_passthrough(&self) -> bool {
+        self.passthrough
     }
 
     pub fn name(&self) -> &str {
@@ -62,7 +62,7 @@ impl Arg {
 
     pub fn get<T: From<String>>(&self) -> Result<Option<T>, Box<dyn Error>> {
         let raw = self.raw();
-        if let None = raw {
+        if raw.is_none() {
             return Ok(None);
         }
         Ok(Some(T::from(raw.unwrap())))
@@ -106,27 +106,17 @@ impl fmt::Display for FlagQuery {
 }
 
 pub fn query_flag_spec<'a>(needle: &FlagQuery, haystack: &'a FlagSpecSet) -> Option<&'a FlagSpec> {
-    for entry in haystack.iter() {
-        if match needle {
-            FlagQuery::Name(ref s) => *s == entry.id.name,
-            FlagQuery::Short(ref c) => *c == entry.id.short,
-        } {
-            return Some(&entry);
-        }
-    }
-    return None;
+    haystack.iter().find(|entry| match needle {
+        FlagQuery::Name(ref s) => *s == entry.id.name,
+        FlagQuery::Short(ref c) => *c == entry.id.short,
+    })
 }
 
 pub fn query_flag<'a>(needle: &FlagQuery, haystack: &'a FlagSet) -> Option<&'a Flag<'a>> {
-    for entry in haystack.iter() {
-        if match needle {
-            FlagQuery::Name(ref s) => *s == entry.spec.id.name,
-            FlagQuery::Short(ref c) => *c == entry.spec.id.short,
-        } {
-            return Some(&entry);
-        }
-    }
-    return None;
+    haystack.iter().find(|entry| match needle {
+        FlagQuery::Name(ref s) => *s == entry.spec.id.name,
+        FlagQuery::Short(ref c) => *c == entry.spec.id.short,
+    })
 }
 
 /// Use FlagSpec to configure command line options for Commands
@@ -143,6 +133,14 @@ impl FlagSpec {
         FlagSpec { id, arg_spec, help: help.to_owned() }
     }
 
+    pub fn name(&self) -> &str {
+        &self.id.name
+    }
+
+    pub fn short(&self) -> char {
+        self.id.short
+    }
+
     pub fn get_arg_spec(&self) -> &ArgSpec {
         &self.arg_spec
     }
@@ -172,7 +170,7 @@ pub struct Flag<'a> {
 }
 
 impl<'a> Flag<'a> {
-    pub fn new(spec: &FlagSpec, arg: Arg) -> Flag {
+    pub fn new(spec: &FlagSpec, arg: Arg) -> Flag<'_> {
         Flag { spec, arg }
     }
 
@@ -185,7 +183,7 @@ impl<'a> Flag<'a> {
             Arg::Optional(_) => { Arg::Optional(arg) },
             Arg::Required(_) => { Arg::Required(arg.expect("Passed None to Arg::Required")) },
             _ => {
-                if let Some(_) = arg {
+                if arg.is_some() {
                     return Err("Trying to set value of Arg::None");
                 }
                 Arg::None
@@ -216,21 +214,31 @@ pub fn is_long(flag_text: &str) -> bool {
     flag_text.starts_with("--") && flag_text.len() > 3
 }
 
-/// check if a string is a short flag
+/// check if a string is a short flag. A dash followed by a digit, a '.' or
+/// a '(' is a negative operand (e.g. -5, -.5, -(1+2)) and not a flag.
 pub fn is_short(flag_text: &str) -> bool {
-    flag_text.starts_with("-") && flag_text.len() > 1
+    match flag_text.strip_prefix('-').and_then(|rest| rest.chars().next()) {
+        Some(c) => !(c.is_ascii_digit() || c == '.' || c == '('),
+        None => false,
+    }
+}
+
+/// check if a string is the "--" marker that ends flag parsing; every token
+/// after it is an operand
+pub fn is_end_of_flags(flag_text: &str) -> bool {
+    flag_text == "--"
 }
 
 /// check if a string is a flag
 pub fn is_flag(flag_text: &str) -> bool {
-    is_long(&flag_text) || is_short(&flag_text)
+    is_long(flag_text) || is_short(flag_text)
 }
 
 /// convert text string to flag query; if text is not a flag, return None
 pub fn extract_flag(flag_text: &str) -> Option<FlagQuery> {
-    if is_long(&flag_text) {
+    if is_long(flag_text) {
         Some(FlagQuery::Name(flag_text.strip_prefix("--").unwrap().to_string()))
-    } else if is_short(&flag_text) {
+    } else if is_short(flag_text) {
         // short flags are complicated
         // you can have the follwing forms:
         // 1) -a [optarg] e.g. -a myarg
@@ -1,3 +1,10 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
 pub type OperandList = Vec<Operand>;
 
 #[derive(Debug)]
@@ -16,23 +23,272 @@ impl std::fmt::Display for MissingOperandError {
 
 impl std::error::Error for MissingOperandError {}
 
+/// An operand that could not be converted. Holds the operand, the type that
+/// was expected and why the conversion failed.
+#[derive(Debug)]
+pub struct OperandTypeError(pub Operand, pub String, pub String);
+
+impl fmt::Display for OperandTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.position() {
+            0 => write!(f, "Error: operand '{}'", self.0.value())?,
+            position => write!(f, "Error: operand {} '{}'", position, self.0.value())?,
+        }
+        write!(f, " is not a valid {}: {}", self.1, self.2)
+    }
+}
+
+impl Error for OperandTypeError {}
+
 #[derive(Clone, Debug)]
 pub struct Operand {
     value: String,
+    position: usize,
 }
 
 impl Operand {
     pub fn new(value: &str) -> Operand {
-        Operand { value: value.into() }
+        Operand { value: value.into(), position: 0 }
+    }
+
+    /// An operand that knows its 1-based position on the command line, so
+    /// that conversion errors can point at it
+    pub fn at(value: &str, position: usize) -> Operand {
+        Operand { value: value.into(), position }
     }
 
     pub fn value(&self) -> &str {
         &self.value
     }
 
-    pub fn value_as<T>(&self) -> Result<T, <T as std::str::FromStr>::Err>
-        where T: std::str::FromStr + std::clone::Clone
+    /// 1-based position on the command line, or 0 if unknown
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn value_as<T>(&self) -> Result<T, OperandTypeError>
+        where T: FromStr, <T as FromStr>::Err: fmt::Display
+    {
+        self.value.parse().map_err(|e: T::Err| self.error(type_name::<T>(), &e.to_string()))
+    }
+
+    /// a path, which does not need to exist
+    pub fn as_path(&self) -> Result<PathBuf, OperandTypeError> {
+        if self.value.is_empty() {
+            return Err(self.error("path", "it is empty"));
+        }
+        Ok(PathBuf::from(&self.value))
+    }
+
+    /// a path to a file or directory that exists
+    pub fn as_existing_path(&self) -> Result<PathBuf, OperandTypeError> {
+        let path = self.as_path()?;
+        if !path.exists() {
+            return Err(self.error("path", "it does not exist"));
+        }
+        Ok(path)
+    }
+
+    /// a path to an existing file
+    pub fn as_file(&self) -> Result<PathBuf, OperandTypeError> {
+        let path = self.as_existing_path()?;
+        if !path.is_file() {
+            return Err(self.error("file", "it is not a file"));
+        }
+        Ok(path)
+    }
+
+    /// a path to an existing directory
+    pub fn as_dir(&self) -> Result<PathBuf, OperandTypeError> {
+        let path = self.as_existing_path()?;
+        if !Path::new(&path).is_dir() {
+            return Err(self.error("directory", "it is not a directory"));
+        }
+        Ok(path)
+    }
+
+    /// A duration like 5s, 2m or 1h30m. Units are ms, s, m, h and d, and a
+    /// number without a unit is in seconds.
+    pub fn as_duration(&self) -> Result<Duration, OperandTypeError> {
+        parse_duration(&self.value).map_err(|reason| self.error("duration (e.g. 5s, 2m)", &reason))
+    }
+
+    /// A byte count like 512, 10kB or 10MiB. Decimal units (kB, MB, GB, TB)
+    /// are powers of 1000, binary units (KiB, MiB, GiB, TiB) powers of 1024.
+    pub fn as_byte_size(&self) -> Result<u64, OperandTypeError> {
+        parse_byte_size(&self.value).map_err(|reason| self.error("byte size (e.g. 10MiB)", &reason))
+    }
+
+    /// A range of integers, `1..10` excluding or `1..=10` including the end
+    pub fn as_range(&self) -> Result<Range<i64>, OperandTypeError> {
+        parse_range(&self.value).map_err(|reason| self.error("range (e.g. 1..10)", &reason))
+    }
+
+    /// yes/no, y/n, true/false, on/off or 1/0
+    pub fn as_bool(&self) -> Result<bool, OperandTypeError> {
+        match self.value.to_lowercase().as_str() {
+            "yes" | "y" | "true" | "on" | "1" => Ok(true),
+            "no" | "n" | "false" | "off" | "0" => Ok(false),
+            _ => Err(self.error("boolean (yes or no)", "expected yes, no, true, false, on or off")),
+        }
+    }
+
+    /// Comma separated values, each converted with FromStr. Whitespace around
+    /// values is ignored and an empty operand is an empty list.
+    pub fn as_list<T>(&self) -> Result<Vec<T>, OperandTypeError>
+        where T: FromStr, <T as FromStr>::Err: fmt::Display
     {
-        self.value.clone().parse()
+        if self.value.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let expected = format!("list of {}", type_name::<T>());
+        self.value
+            .split(',')
+            .enumerate()
+            .map(|(idx, item)| {
+                item.trim().parse().map_err(|e: T::Err| {
+                    self.error(&expected, &format!("item {} '{}': {}", idx + 1, item.trim(), e))
+                })
+            })
+            .collect()
+    }
+
+    fn error(&self, expected: &str, reason: &str) -> OperandTypeError {
+        OperandTypeError(self.clone(), expected.into(), reason.into())
+    }
+}
+
+/// the name of a type without its module path
+fn type_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// split text into a leading number and the rest
+fn split_number(text: &str) -> (&str, &str) {
+    let end = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    text.split_at(end)
+}
+
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    if text.is_empty() {
+        return Err("it is empty".into());
+    }
+
+    let mut seconds = 0.0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let (number, after) = split_number(rest);
+        let value: f64 = number.parse().map_err(|_| format!("expected a number at '{}'", rest))?;
+
+        let unit_len = after.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(after.len());
+        let (unit, after) = after.split_at(unit_len);
+        let scale = match unit {
+            "ms" => 0.001,
+            "" | "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            "d" => 86400.0,
+            _ => return Err(format!("unknown unit '{}', expected ms, s, m, h or d", unit)),
+        };
+        // only the last number may leave out its unit
+        if unit.is_empty() && !after.is_empty() {
+            return Err(format!("missing unit after '{}'", number));
+        }
+
+        seconds += value * scale;
+        rest = after;
+    }
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| "it is too long".into())
+}
+
+fn parse_byte_size(text: &str) -> Result<u64, String> {
+    let (number, unit) = split_number(text.trim());
+    let value: f64 = number.parse().map_err(|_| String::from("expected a number"))?;
+
+    let scale: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "kib" => 1 << 10,
+        "m" | "mb" => 1000 * 1000,
+        "mib" => 1 << 20,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "gib" => 1 << 30,
+        "t" | "tb" => 1000 * 1000 * 1000 * 1000,
+        "tib" => 1 << 40,
+        _ => return Err(format!("unknown unit '{}', expected B, kB, KiB, MB, MiB, GB, GiB, TB or TiB", unit)),
+    };
+
+    let bytes = value * scale as f64;
+    if bytes.fract() != 0.0 {
+        return Err("it is not a whole number of bytes".into());
+    }
+    if bytes >= u64::MAX as f64 {
+        return Err("it is too large".into());
+    }
+    Ok(bytes as u64)
+}
+
+fn parse_range(text: &str) -> Result<Range<i64>, String> {
+    let (start, end, inclusive) = match text.split_once("..=") {
+        Some((start, end)) => (start, end, true),
+        None => match text.split_once("..") {
+            Some((start, end)) => (start, end, false),
+            None => return Err("expected start..end or start..=end".into()),
+        },
+    };
+
+    let start: i64 = start.trim().parse().map_err(|e| format!("bad start: {}", e))?;
+    let mut end: i64 = end.trim().parse().map_err(|e| format!("bad end: {}", e))?;
+    if inclusive {
+        end = end.checked_add(1).ok_or_else(|| String::from("the end is too large"))?;
+    }
+    if end < start {
+        return Err("the end comes before the start".into());
+    }
+    Ok(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Duration::from_secs(5), Operand::new("5s").as_duration().unwrap());
+        assert_eq!(Duration::from_secs(5400), Operand::new("1h30m").as_duration().unwrap());
+        assert_eq!(Duration::from_millis(1500), Operand::new("1.5").as_duration().unwrap());
+        assert!(Operand::new("5x").as_duration().is_err());
+        assert!(Operand::new("5 2m").as_duration().is_err());
+
+        assert_eq!(10 << 20, Operand::new("10MiB").as_byte_size().unwrap());
+        assert_eq!(1500, Operand::new("1.5kB").as_byte_size().unwrap());
+        assert!(Operand::new("0.5B").as_byte_size().is_err());
+
+        assert_eq!(1..10, Operand::new("1..10").as_range().unwrap());
+        assert_eq!(-2..11, Operand::new("-2..=10").as_range().unwrap());
+        assert!(Operand::new("3..1").as_range().is_err());
+
+        assert!(Operand::new("Yes").as_bool().unwrap());
+        assert!(!Operand::new("off").as_bool().unwrap());
+
+        assert_eq!(vec![1, 2, 3], Operand::new("1, 2,3").as_list::<i32>().unwrap());
+        assert!(Operand::new("").as_list::<i32>().unwrap().is_empty());
+
+        assert!(Operand::new(env!("CARGO_MANIFEST_DIR")).as_dir().is_ok());
+        assert!(Operand::new(env!("CARGO_MANIFEST_DIR")).as_file().is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let error = Operand::at("abc", 2).value_as::<i32>().unwrap_err();
+        assert_eq!("Error: operand 2 'abc' is not a valid i32: invalid digit found in string", error.to_string());
+
+        let error = Operand::at("1,x", 1).as_list::<u8>().unwrap_err();
+        assert!(error.to_string().starts_with("Error: operand 1 '1,x' is not a valid list of u8: item 2 'x'"));
     }
 }
@@ -0,0 +1,447 @@
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+/// A single field value in structured command output
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Value {
+        Value::Int(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Value {
+        Value::Float(x)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::Text(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Text(s)
+    }
+}
+
+/// An ordered list of named fields
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    fields: Vec<(String, Value)>,
+}
+
+impl Record {
+    pub fn new() -> Record {
+        Record { fields: Vec::new() }
+    }
+
+    /// add a field, builder style. Setting an existing name replaces its value.
+    pub fn with<V: Into<Value>>(mut self, name: &str, value: V) -> Record {
+        self.set(name, value);
+        self
+    }
+
+    pub fn set<V: Into<Value>>(&mut self, name: &str, value: V) {
+        let value = value.into();
+        match self.fields.iter_mut().find(|(n, _)| n == name) {
+            Some(field) => field.1 = value,
+            None => self.fields.push((name.into(), value)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    pub fn fields(&self) -> &[(String, Value)] {
+        &self.fields
+    }
+}
+
+/// Rows of values under a fixed set of column names
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Table {
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    pub fn new(columns: &[&str]) -> Table {
+        Table {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// add a row. Missing trailing values are filled with Value::Null and
+    /// extra values are dropped, so every row matches the columns.
+    pub fn push_row(&mut self, mut row: Vec<Value>) {
+        row.resize(self.columns.len(), Value::Null);
+        self.rows.push(row);
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn rows(&self) -> &[Vec<Value>] {
+        &self.rows
+    }
+
+    /// Build a table out of records; the columns are the union of all field
+    /// names in order of first appearance.
+    pub fn from_records(records: &[Record]) -> Table {
+        let mut columns: Vec<String> = Vec::new();
+        for record in records {
+            for (name, _) in record.fields() {
+                if !columns.contains(name) {
+                    columns.push(name.clone());
+                }
+            }
+        }
+
+        let rows = records
+            .iter()
+            .map(|r| {
+                columns
+                    .iter()
+                    .map(|c| r.get(c).cloned().unwrap_or(Value::Null))
+                    .collect()
+            })
+            .collect();
+
+        Table { columns, rows }
+    }
+}
+
+/// Structured data a command can hand back to the shell for rendering,
+/// instead of printing text itself.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Output {
+    Record(Record),
+    List(Vec<Record>),
+    Table(Table),
+}
+
+#[derive(Debug)]
+pub struct UnknownOutputFormatError(pub String);
+
+impl fmt::Display for UnknownOutputFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown output format '{}', expected text, table or json",
+            self.0
+        )
+    }
+}
+
+impl Error for UnknownOutputFormatError {}
+
+/// How the shell renders Output
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Table,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = UnknownOutputFormatError;
+
+    fn from_str(s: &str) -> Result<OutputFormat, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(UnknownOutputFormatError(s.into())),
+        }
+    }
+}
+
+impl Output {
+    pub fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Text => self.render_text(),
+            OutputFormat::Table => render_table(&self.to_table(), true),
+            OutputFormat::Json => self.render_json(),
+        }
+    }
+
+    fn to_table(&self) -> Table {
+        match self {
+            Output::Record(record) => {
+                let mut table = Table::new(&["name", "value"]);
+                for (name, value) in record.fields() {
+                    table.push_row(vec![Value::Text(name.clone()), value.clone()]);
+                }
+                table
+            }
+            Output::List(records) => Table::from_records(records),
+            Output::Table(table) => table.clone(),
+        }
+    }
+
+    /// "name: value" lines for records, aligned columns for tables
+    fn render_text(&self) -> String {
+        match self {
+            Output::Record(record) => render_record(record),
+            Output::List(records) => records
+                .iter()
+                .map(render_record)
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Output::Table(table) => render_table(table, false),
+        }
+    }
+
+    fn render_json(&self) -> String {
+        let mut json = String::new();
+        match self {
+            Output::Record(record) => write_json_record(&mut json, record.fields().iter()),
+            Output::List(records) => {
+                json.push('[');
+                for (idx, record) in records.iter().enumerate() {
+                    if idx > 0 {
+                        json.push(',');
+                    }
+                    write_json_record(&mut json, record.fields().iter());
+                }
+                json.push(']');
+            }
+            Output::Table(table) => {
+                json.push('[');
+                for (idx, row) in table.rows().iter().enumerate() {
+                    if idx > 0 {
+                        json.push(',');
+                    }
+                    let fields: Vec<(String, Value)> = table
+                        .columns()
+                        .iter()
+                        .cloned()
+                        .zip(row.iter().cloned())
+                        .collect();
+                    write_json_record(&mut json, fields.iter());
+                }
+                json.push(']');
+            }
+        }
+        json.push('\n');
+        json
+    }
+}
+
+/// Pad text with spaces on the left until it is width characters wide
+pub fn pad_left(text: &str, width: usize) -> String {
+    format!(
+        "{}{}",
+        " ".repeat(width.saturating_sub(text.chars().count())),
+        text
+    )
+}
+
+/// Pad text with spaces on the right until it is width characters wide
+pub fn pad_right(text: &str, width: usize) -> String {
+    format!(
+        "{}{}",
+        text,
+        " ".repeat(width.saturating_sub(text.chars().count()))
+    )
+}
+
+fn render_record(record: &Record) -> String {
+    let name_width = record
+        .fields()
+        .iter()
+        .map(|(n, _)| n.chars().count() + 1)
+        .max()
+        .unwrap_or(0);
+
+    let mut text = String::new();
+    for (name, value) in record.fields() {
+        writeln!(
+            text,
+            "{} {}",
+            pad_right(&format!("{}:", name), name_width),
+            value
+        )
+        .unwrap();
+    }
+    text
+}
+
+/// Render a table with every column as wide as its widest cell. With
+/// borders, the table is boxed in and the header is underlined.
+fn render_table(table: &Table, borders: bool) -> String {
+    let cells: Vec<Vec<String>> = table
+        .rows()
+        .iter()
+        .map(|row| row.iter().map(|v| v.to_string()).collect())
+        .collect();
+
+    let mut widths: Vec<usize> = table.columns().iter().map(|c| c.chars().count()).collect();
+    for row in cells.iter() {
+        for (idx, cell) in row.iter().enumerate() {
+            widths[idx] = std::cmp::max(widths[idx], cell.chars().count());
+        }
+    }
+
+    let separator = widths
+        .iter()
+        .map(|w| "-".repeat(w + 2))
+        .collect::<Vec<String>>()
+        .join("+");
+    let separator = format!("+{}+\n", separator);
+
+    let line = |row: &[String]| -> String {
+        let padded: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| pad_right(cell, *width))
+            .collect();
+        if borders {
+            format!("| {} |\n", padded.join(" | "))
+        } else {
+            format!("{}\n", padded.join("  ").trim_end())
+        }
+    };
+
+    let mut text = String::new();
+    if borders {
+        text.push_str(&separator);
+    }
+    text.push_str(&line(table.columns()));
+    if borders {
+        text.push_str(&separator);
+    }
+    for row in cells.iter() {
+        text.push_str(&line(row));
+    }
+    if borders && !cells.is_empty() {
+        text.push_str(&separator);
+    }
+    text
+}
+
+fn write_json_record<'a, I>(json: &mut String, fields: I)
+where
+    I: Iterator<Item = &'a (String, Value)>,
+{
+    json.push('{');
+    for (idx, (name, value)) in fields.enumerate() {
+        if idx > 0 {
+            json.push(',');
+        }
+        write_json_string(json, name);
+        json.push(':');
+        write_json_value(json, value);
+    }
+    json.push('}');
+}
+
+fn write_json_value(json: &mut String, value: &Value) {
+    match value {
+        Value::Null => json.push_str("null"),
+        Value::Bool(b) => write!(json, "{}", b).unwrap(),
+        Value::Int(i) => write!(json, "{}", i).unwrap(),
+        Value::Float(x) if x.is_finite() => write!(json, "{:?}", x).unwrap(),
+        Value::Float(_) => json.push_str("null"),
+        Value::Text(s) => write_json_string(json, s),
+    }
+}
+
+fn write_json_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let record = Record::new()
+            .with("name", "say \"hi\"\n")
+            .with("count", 2i64)
+            .with("ratio", 0.5)
+            .with("ok", true)
+            .with("none", Value::Null);
+        assert_eq!(
+            "{\"name\":\"say \\\"hi\\\"\\n\",\"count\":2,\"ratio\":0.5,\"ok\":true,\"none\":null}\n",
+            Output::Record(record).render(OutputFormat::Json)
+        );
+
+        let list = vec![
+            Record::new().with("a", 1i64),
+            Record::new().with("a", 2i64).with("b", "x"),
+        ];
+        assert_eq!(
+            "[{\"a\":1},{\"a\":2,\"b\":\"x\"}]\n",
+            Output::List(list).render(OutputFormat::Json)
+        );
+    }
+
+    #[test]
+    fn table() {
+        let mut table = Table::new(&["name", "size"]);
+        table.push_row(vec!["a".into(), 10i64.into()]);
+        table.push_row(vec!["longer".into()]);
+
+        assert_eq!(
+            "name    size\na       10\nlonger\n",
+            Output::Table(table.clone()).render(OutputFormat::Text)
+        );
+        assert_eq!(
+            "+--------+------+\n\
+             | name   | size |\n\
+             +--------+------+\n\
+             | a      | 10   |\n\
+             | longer |      |\n\
+             +--------+------+\n",
+            Output::Table(table).render(OutputFormat::Table)
+        );
+    }
+}
@@ -0,0 +1,505 @@
+//! Generate reference documentation and shell completion scripts from the
+//! commands registered with a Shell. All generators describe one-shot mode,
+//! i.e. `<program> <command> [flags] [operands]`, and skip hidden commands.
+use crate::command::flag::{ArgSpec, FlagSpec, FlagSpecSet};
+use crate::command::{self, Command, ReturnCode};
+use crate::shell::{self, Context, Shell};
+use crate::shell_print;
+use std::env;
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// name of the builtin command returned by config()
+pub const DOCS_COMMAND: &str = "docs";
+
+/// help text for the global output flag
+const OUTPUT_FLAG_HELP: &str = "Render structured results as text, table or json";
+const OUTPUT_FORMATS: [&str; 3] = ["text", "table", "json"];
+
+/// Every format the generators can produce
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DocFormat {
+    Man,
+    Markdown,
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Debug)]
+pub struct UnknownDocFormatError(pub String);
+
+impl fmt::Display for UnknownDocFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown documentation format '{}', expected man, markdown, bash, zsh or fish",
+            self.0
+        )
+    }
+}
+
+impl Error for UnknownDocFormatError {}
+
+impl FromStr for DocFormat {
+    type Err = UnknownDocFormatError;
+
+    fn from_str(s: &str) -> Result<DocFormat, Self::Err> {
+        match s {
+            "man" => Ok(DocFormat::Man),
+            "markdown" | "md" => Ok(DocFormat::Markdown),
+            "bash" => Ok(DocFormat::Bash),
+            "zsh" => Ok(DocFormat::Zsh),
+            "fish" => Ok(DocFormat::Fish),
+            _ => Err(UnknownDocFormatError(s.into())),
+        }
+    }
+}
+
+/// Generate documentation for shell in the given format. The program name is
+/// the name of the executable that runs the shell.
+pub fn generate(shell: &Shell, program: &str, format: DocFormat) -> String {
+    match format {
+        DocFormat::Man => man_page(shell, program),
+        DocFormat::Markdown => markdown(shell, program),
+        DocFormat::Bash => bash_completion(shell, program),
+        DocFormat::Zsh => zsh_completion(shell, program),
+        DocFormat::Fish => fish_completion(shell, program),
+    }
+}
+
+/// A hidden builtin that prints generated documentation, e.g.
+/// `docs man > cli.1` or `docs bash > /etc/bash_completion.d/cli`.
+pub fn config() -> command::Config {
+    command::Config::new(
+        DOCS_COMMAND,
+        FlagSpecSet::new(),
+        "Print a man page, markdown reference or bash/zsh/fish completion script",
+        |command: &Command,
+         shell: &Shell,
+         _context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            let format = match command.operands().first() {
+                Some(operand) => operand.value_as::<DocFormat>()?,
+                None => return Err(Box::new(UnknownDocFormatError(String::new()))),
+            };
+
+            shell_print!("{}", generate(shell, &program_name(), format));
+            Ok(ReturnCode::Ok)
+        },
+    )
+    .hidden()
+}
+
+/// file name of the running executable
+fn program_name() -> String {
+    env::args()
+        .next()
+        .as_ref()
+        .and_then(|arg0| Path::new(arg0).file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "cli".into())
+}
+
+/// visible commands sorted by name, so that output is deterministic
+fn sorted_commands(shell: &Shell) -> Vec<command::Config> {
+    let mut commands: Vec<command::Config> = shell
+        .commands()
+        .into_values()
+        .filter(|c| !c.is_hidden())
+        .collect();
+    commands.sort_by(|a, b| a.name().cmp(b.name()));
+    commands
+}
+
+fn sorted_flags(config: &command::Config) -> Vec<&FlagSpec> {
+    let mut flags: Vec<&FlagSpec> = config.get_flags().iter().collect();
+    flags.sort_by(|a, b| a.name().cmp(b.name()));
+    flags
+}
+
+/// placeholder for the flag argument in usage text, if it takes one
+fn arg_usage(spec: &FlagSpec) -> Option<&'static str> {
+    match spec.get_arg_spec() {
+        ArgSpec::None => None,
+        ArgSpec::Optional => Some("[ARG]"),
+        ArgSpec::Required => Some("ARG"),
+    }
+}
+
+/// Generate a roff man page, section 1
+pub fn man_page(shell: &Shell, program: &str) -> String {
+    let mut man = String::new();
+
+    writeln!(man, ".TH {} 1", roff_escape(&program.to_uppercase())).unwrap();
+    writeln!(man, ".SH NAME").unwrap();
+    writeln!(
+        man,
+        "{} \\- {}",
+        roff_escape(program),
+        roff_escape(shell.description())
+    )
+    .unwrap();
+    writeln!(man, ".SH SYNOPSIS").unwrap();
+    writeln!(man, ".B {}", roff_escape(program)).unwrap();
+    writeln!(
+        man,
+        "[\\fB\\-\\-{}\\fR \\fIFORMAT\\fR] \\fICOMMAND\\fR [\\fIFLAGS\\fR] [\\fIOPERANDS\\fR...]",
+        shell::OUTPUT_FLAG
+    )
+    .unwrap();
+    writeln!(man, ".SH DESCRIPTION").unwrap();
+    writeln!(man, "{}", roff_escape(shell.description())).unwrap();
+    writeln!(man, "Without a command, an interactive shell is started.").unwrap();
+
+    writeln!(man, ".SH GLOBAL OPTIONS").unwrap();
+    writeln!(man, ".TP").unwrap();
+    writeln!(man, "\\fB\\-\\-{}\\fR \\fIFORMAT\\fR", shell::OUTPUT_FLAG).unwrap();
+    writeln!(man, "{}", roff_escape(OUTPUT_FLAG_HELP)).unwrap();
+
+    writeln!(man, ".SH COMMANDS").unwrap();
+    for config in sorted_commands(shell) {
+        writeln!(man, ".TP").unwrap();
+        writeln!(man, ".B {}", roff_escape(config.name())).unwrap();
+        writeln!(man, "{}", roff_escape(config.help())).unwrap();
+
+        let flags = sorted_flags(&config);
+        if flags.is_empty() {
+            continue;
+        }
+        writeln!(man, ".RS").unwrap();
+        for spec in flags {
+            writeln!(man, ".TP").unwrap();
+            write!(
+                man,
+                "\\fB\\-{}\\fR, \\fB\\-\\-{}\\fR",
+                roff_escape(&spec.short().to_string()),
+                roff_escape(spec.name())
+            )
+            .unwrap();
+            if let Some(arg) = arg_usage(spec) {
+                write!(man, " \\fI{}\\fR", arg).unwrap();
+            }
+            writeln!(man).unwrap();
+            writeln!(man, "{}", roff_escape(&spec.help())).unwrap();
+        }
+        writeln!(man, ".RE").unwrap();
+    }
+
+    man
+}
+
+/// Generate a markdown command reference
+pub fn markdown(shell: &Shell, program: &str) -> String {
+    let mut md = String::new();
+
+    writeln!(md, "# {}\n", program).unwrap();
+    writeln!(md, "{}\n", shell.description()).unwrap();
+    writeln!(md, "## Usage\n").unwrap();
+    writeln!(
+        md,
+        "    {} [--{} {}] <command> [flags] [operands]\n",
+        program,
+        shell::OUTPUT_FLAG,
+        OUTPUT_FORMATS.join("|")
+    )
+    .unwrap();
+    writeln!(md, "Without a command, an interactive shell is started.\n").unwrap();
+    writeln!(md, "## Commands").unwrap();
+
+    for config in sorted_commands(shell) {
+        writeln!(md, "\n### {}\n", config.name()).unwrap();
+        writeln!(md, "{}", config.help()).unwrap();
+
+        let flags = sorted_flags(&config);
+        if flags.is_empty() {
+            continue;
+        }
+        writeln!(md, "\n| Flag | Argument | Description |").unwrap();
+        writeln!(md, "|------|----------|-------------|").unwrap();
+        for spec in flags {
+            let arg = match spec.get_arg_spec() {
+                ArgSpec::None => "",
+                ArgSpec::Optional => "optional",
+                ArgSpec::Required => "required",
+            };
+            writeln!(
+                md,
+                "| `-{}`, `--{}` | {} | {} |",
+                spec.short(),
+                spec.name(),
+                arg,
+                spec.help().replace('|', "\\|")
+            )
+            .unwrap();
+        }
+    }
+
+    md
+}
+
+/// Generate a bash completion script, to be sourced or installed into
+/// bash_completion.d
+pub fn bash_completion(shell: &Shell, program: &str) -> String {
+    let function = format!("_{}", shell_identifier(program));
+    let commands = sorted_commands(shell);
+    let names: Vec<&str> = commands.iter().map(|c| c.name()).collect();
+    let global = format!("--{}", shell::OUTPUT_FLAG);
+
+    let mut script = String::new();
+    writeln!(script, "{}() {{", function).unwrap();
+    writeln!(script, "    local cur prev cmd i").unwrap();
+    writeln!(script, "    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"").unwrap();
+    writeln!(script, "    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"").unwrap();
+    writeln!(script).unwrap();
+    writeln!(script, "    if [ \"$prev\" = \"{}\" ]; then", global).unwrap();
+    writeln!(
+        script,
+        "        COMPREPLY=( $(compgen -W \"{}\" -- \"$cur\") )",
+        OUTPUT_FORMATS.join(" ")
+    )
+    .unwrap();
+    writeln!(script, "        return").unwrap();
+    writeln!(script, "    fi").unwrap();
+    writeln!(script).unwrap();
+    writeln!(
+        script,
+        "    # the command is the first word that is not the global flag"
+    )
+    .unwrap();
+    writeln!(script, "    cmd=\"\"").unwrap();
+    writeln!(script, "    for (( i=1; i < COMP_CWORD; i++ )); do").unwrap();
+    writeln!(script, "        case \"${{COMP_WORDS[i]}}\" in").unwrap();
+    writeln!(script, "            {}) (( i++ )) ;;", global).unwrap();
+    writeln!(script, "            -*) ;;").unwrap();
+    writeln!(
+        script,
+        "            *) cmd=\"${{COMP_WORDS[i]}}\"; break ;;"
+    )
+    .unwrap();
+    writeln!(script, "        esac").unwrap();
+    writeln!(script, "    done").unwrap();
+    writeln!(script).unwrap();
+    writeln!(script, "    case \"$cmd\" in").unwrap();
+    writeln!(script, "        \"\")").unwrap();
+    writeln!(
+        script,
+        "            COMPREPLY=( $(compgen -W \"{} {}\" -- \"$cur\") ) ;;",
+        names.join(" "),
+        global
+    )
+    .unwrap();
+    for config in commands.iter() {
+        let mut words: Vec<String> = Vec::new();
+        for spec in sorted_flags(config) {
+            words.push(format!("--{}", spec.name()));
+            words.push(format!("-{}", spec.short()));
+        }
+        words.push(global.clone());
+        writeln!(script, "        {})", bash_quote(config.name())).unwrap();
+        writeln!(script, "            if [[ \"$cur\" == -* ]]; then").unwrap();
+        writeln!(
+            script,
+            "                COMPREPLY=( $(compgen -W \"{}\" -- \"$cur\") )",
+            words.join(" ")
+        )
+        .unwrap();
+        writeln!(script, "            else").unwrap();
+        writeln!(
+            script,
+            "                COMPREPLY=( $(compgen -f -- \"$cur\") )"
+        )
+        .unwrap();
+        writeln!(script, "            fi ;;").unwrap();
+    }
+    writeln!(script, "    esac").unwrap();
+    writeln!(script, "}}").unwrap();
+    writeln!(script, "complete -F {} {}", function, program).unwrap();
+
+    script
+}
+
+/// Generate a zsh completion script, to be installed as _<program> on fpath
+pub fn zsh_completion(shell: &Shell, program: &str) -> String {
+    let function = format!("_{}", shell_identifier(program));
+    let global = format!(
+        "'--{}[{}]:format:({})'",
+        shell::OUTPUT_FLAG,
+        zsh_escape(OUTPUT_FLAG_HELP),
+        OUTPUT_FORMATS.join(" ")
+    );
+
+    let mut script = String::new();
+    writeln!(script, "#compdef {}\n", program).unwrap();
+    writeln!(script, "{}() {{", function).unwrap();
+    writeln!(script, "    local -a commands").unwrap();
+    writeln!(script, "    commands=(").unwrap();
+    for config in sorted_commands(shell) {
+        writeln!(
+            script,
+            "        '{}:{}'",
+            zsh_escape(config.name()).replace(':', "\\:"),
+            zsh_escape(config.help())
+        )
+        .unwrap();
+    }
+    writeln!(script, "    )\n").unwrap();
+    writeln!(script, "    _arguments -C \\").unwrap();
+    writeln!(script, "        {} \\", global).unwrap();
+    writeln!(script, "        '1:command:->command' \\").unwrap();
+    writeln!(script, "        '*::arg:->args'\n").unwrap();
+    writeln!(script, "    case $state in").unwrap();
+    writeln!(script, "        command)").unwrap();
+    writeln!(script, "            _describe 'command' commands ;;").unwrap();
+    writeln!(script, "        args)").unwrap();
+    writeln!(script, "            case $words[1] in").unwrap();
+    for config in sorted_commands(shell) {
+        writeln!(script, "                {})", bash_quote(config.name())).unwrap();
+        writeln!(script, "                    _arguments \\").unwrap();
+        for spec in sorted_flags(&config) {
+            let arg = match spec.get_arg_spec() {
+                ArgSpec::None => "",
+                ArgSpec::Optional => "::value:",
+                ArgSpec::Required => ":value:",
+            };
+            writeln!(
+                script,
+                "                        '(-{short} --{name})'{{-{short},--{name}}}'[{help}]{arg}' \\",
+                short = spec.short(),
+                name = spec.name(),
+                help = zsh_escape(&spec.help()),
+                arg = arg,
+            )
+            .unwrap();
+        }
+        writeln!(script, "                        {} \\", global).unwrap();
+        writeln!(script, "                        '*:operand:_files' ;;").unwrap();
+    }
+    writeln!(script, "            esac ;;").unwrap();
+    writeln!(script, "    esac").unwrap();
+    writeln!(script, "}}\n").unwrap();
+    writeln!(script, "{} \"$@\"", function).unwrap();
+
+    script
+}
+
+/// Generate a fish completion script, to be installed into
+/// ~/.config/fish/completions/<program>.fish
+pub fn fish_completion(shell: &Shell, program: &str) -> String {
+    let mut script = String::new();
+
+    writeln!(
+        script,
+        "complete -c {} -l {} -x -a '{}' -d '{}'",
+        program,
+        shell::OUTPUT_FLAG,
+        OUTPUT_FORMATS.join(" "),
+        fish_escape(OUTPUT_FLAG_HELP)
+    )
+    .unwrap();
+
+    for config in sorted_commands(shell) {
+        writeln!(
+            script,
+            "complete -c {} -n '__fish_use_subcommand' -f -a '{}' -d '{}'",
+            program,
+            fish_escape(config.name()),
+            fish_escape(config.help())
+        )
+        .unwrap();
+
+        for spec in sorted_flags(&config) {
+            let arg = match spec.get_arg_spec() {
+                ArgSpec::Required => " -r",
+                _ => "",
+            };
+            writeln!(
+                script,
+                "complete -c {} -n '__fish_seen_subcommand_from {}' -s {} -l {}{} -d '{}'",
+                program,
+                fish_escape(config.name()),
+                spec.short(),
+                spec.name(),
+                arg,
+                fish_escape(&spec.help())
+            )
+            .unwrap();
+        }
+    }
+
+    script
+}
+
+/// turn a program name into something usable as a shell function name
+fn shell_identifier(program: &str) -> String {
+    program
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn roff_escape(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    // a leading '.' or '\'' would be read as a roff request
+    if escaped.starts_with(['.', '\'']) {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// quote a word for use as a case pattern
+fn bash_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// escape text inside a single quoted zsh _arguments spec
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+}
+
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::CommandSet;
+
+    fn noop(_: &Command, _: &Shell, _: &mut Context) -> Result<ReturnCode, Box<dyn Error>> {
+        Ok(ReturnCode::Ok)
+    }
+
+    #[test]
+    fn hidden_commands_are_left_out() {
+        let mut commands = CommandSet::new();
+        let list = command::Config::new("list", FlagSpecSet::new(), "List things", noop);
+        let secret = command::Config::new("secret", FlagSpecSet::new(), "Hidden", noop).hidden();
+        commands.insert(list.name().into(), list);
+        commands.insert(secret.name().into(), secret);
+        let shell = Shell::new(commands, "Test shell");
+
+        for format in [
+            DocFormat::Man,
+            DocFormat::Markdown,
+            DocFormat::Bash,
+            DocFormat::Zsh,
+            DocFormat::Fish,
+        ] {
+            let text = generate(&shell, "test", format);
+            assert!(text.contains("list"), "{:?}", format);
+            assert!(!text.contains("secret"), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn roff() {
+        assert_eq!("\\-\\-all \\e", roff_escape("--all \\"));
+        assert_eq!("\\&.start", roff_escape(".start"));
+    }
+}
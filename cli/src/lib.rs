@@ -1,2 +1,5 @@
+pub mod builtins;
+pub mod calc;
 pub mod command;
+pub mod docs;
 pub mod shell;
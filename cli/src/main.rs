@@ -1,8 +1,22 @@
+use cli::builtins::Builtin;
+use cli::calc::{self, CalcError};
+use cli::docs;
 use cli::command::{self, Command};
-use cli::command::flag::{self, FlagSpec, FlagSpecSet};
+use cli::command::flag::{self, FlagQuery, FlagSpec, FlagSpecSet};
 use cli::command::operand::MissingOperandError;
-use cli::shell::{CommandSet, Context, Shell};
+use cli::command::output::{Output, Table};
+use cli::shell::{alias, macros, prompt, CommandSet, Context, ExternalCommands, Shell};
+use cli::shell_println;
+use std::env;
 use std::error::Error;
+use std::path::PathBuf;
+use std::process;
+
+/// rc file in the home directory, run when the interactive shell starts
+const RC_FILE_NAME: &str = ".clirc";
+
+/// file in the home directory holding recorded macros
+const MACRO_FILE_NAME: &str = ".cli_macros";
 
 fn main() {
     // create a config
@@ -36,41 +50,107 @@ fn main() {
                 );
             }
 
-            println!(
-                "{}",
-                operands[0].value_as::<i32>()? + operands[1].value_as::<i32>()?
-            );
+            let mut sum = operands[0].value_as::<i32>()?
+                .checked_add(operands[1].value_as::<i32>()?)
+                .ok_or(CalcError::Overflow)?;
+            if let Some(modulo) = flag::query_flag(&FlagQuery::Name("modulo".into()), command.flags()) {
+                let divisor = modulo.get_arg().raw().unwrap_or_default().parse::<i32>()?;
+                sum = sum.checked_rem_euclid(divisor).ok_or(CalcError::DivideByZero)?;
+            }
+
+            shell_println!("{}", sum);
 
             Ok(command::ReturnCode::Ok)
         },
     );
 
-    let help_config = command::Config::new(
-        "help",
-        FlagSpecSet::new(),
-        "Print this help message",
-        | _command: &Command, shell: &Shell, _context: &mut Context | -> Result<command::ReturnCode, Box<dyn Error>> {
-            println!("{}", shell.help());
+    let mut flag_spec = FlagSpecSet::new();
+    flag_spec.insert(
+        FlagSpec::new("store", 's', flag::ArgSpec::Required,
+            "Save the result into the context under the given name"
+        )
+    );
+    let calc_config = command::Config::new(
+        "calc",
+        flag_spec,
+        "Evaluate an arithmetic expression, e.g. calc (1 + 2) * $x",
+        | command: &Command, _shell: &Shell, context: &mut Context | -> Result<command::ReturnCode, Box<dyn Error>> {
+            let expression = command
+                .operands()
+                .iter()
+                .map(|o| o.value())
+                .collect::<Vec<&str>>()
+                .join(" ");
+
+            let result = calc::evaluate(&expression, context)?;
+            shell_println!("{}", result);
+
+            if let Some(store) = flag::query_flag(&FlagQuery::Name("store".into()), command.flags()) {
+                context.insert(store.get_arg().raw().unwrap(), result.to_string());
+            }
+
             Ok(command::ReturnCode::Ok)
         },
-    );
+    )
+    .no_glob();
 
-    let exit_config = command::Config::new(
-        "exit",
+    let vars_config = command::Config::new(
+        "vars",
         FlagSpecSet::new(),
-        "Quit the command line interface.",
-        | _command: &Command, _shell: &Shell, _context: &mut Context | -> Result<command::ReturnCode, Box<dyn Error>> {
-            Ok(command::ReturnCode::Abort)
+        "List the variables stored in the shell context",
+        | _command: &Command, _shell: &Shell, context: &mut Context | -> Result<command::ReturnCode, Box<dyn Error>> {
+            let mut names: Vec<&String> = context.keys().collect();
+            names.sort();
+
+            let mut table = Table::new(&["name", "value"]);
+            for name in names {
+                table.push_row(vec![name.as_str().into(), context[name].as_str().into()]);
+            }
+
+            Ok(command::ReturnCode::Data(Output::Table(table)))
         },
     );
 
     let mut command_set = CommandSet::new();
     command_set.insert(add_config.name().to_owned(), add_config);
-    command_set.insert(help_config.name().to_owned(), help_config);
-    command_set.insert(exit_config.name().to_owned(), exit_config);
+    command_set.insert(calc_config.name().to_owned(), calc_config);
+    command_set.insert(vars_config.name().to_owned(), vars_config);
+
+    let docs_config = docs::config();
+    command_set.insert(docs_config.name().to_owned(), docs_config);
+
+    for config in [alias::alias_config(), alias::unalias_config(), prompt::config(), macros::record_config(), macros::replay_config()] {
+        command_set.insert(config.name().to_owned(), config);
+    }
 
     let mut context = Context::new();
 
-    let shell = Shell::new(command_set, "Rudimentary general purpose command line interface.");
-    shell.run(&mut context);
+    let mut shell = Shell::new(command_set, "Rudimentary general purpose command line interface.")
+        .with_external_commands(ExternalCommands::new());
+    for builtin in Builtin::ALL {
+        shell = shell.with_builtin(builtin);
+    }
+    if let Some(home) = env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        shell = shell
+            .with_rc_file(home.join(RC_FILE_NAME))
+            .with_macro_file(home.join(MACRO_FILE_NAME));
+    }
+
+    // with arguments, run them as a single command and exit (one-shot mode),
+    // otherwise start the interactive shell
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        shell.run(&mut context);
+        return;
+    }
+
+    let status = match shell.run_args(&args, &mut context) {
+        Ok(code) => code.status(),
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        }
+    };
+    process::exit(status);
 }
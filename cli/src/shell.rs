@@ -1,46 +1,166 @@
-use crate::command::flag::{self, Flag, FlagMissingArgError, FlagSet, UnknownFlagError};
+use crate::command::flag::{self, Flag, FlagMissingArgError, FlagQuery, FlagSet, UnknownFlagError};
 use crate::command::operand::{Operand, OperandList};
+use crate::command::output::{self, OutputFormat};
 use crate::command::{self, Command};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fmt::Write as fmt_Write;
-use std::io::{self, Write};
+use std::fs;
+use crate::{shell_print, shell_println};
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+pub mod alias;
+pub mod external;
+pub mod glob;
+pub mod lexer;
+pub mod macros;
+pub mod matching;
+pub mod prompt;
+pub mod script;
+pub mod server;
+pub mod session;
+
+pub use external::ExternalCommands;
+pub use lexer::SyntaxError;
+pub use matching::{MatchOptions, NameCollisionError};
+pub use server::Server;
+pub use session::Session;
 
-/// default prompt string
-const DEFAULT_PROMPT: &str = "#";
 const CONTEXT_PROMPT_STRING: &str = "prompt";
+/// template of the prompt shown while a command continues on the next line
+const CONTEXT_CONTINUATION_PROMPT: &str = "prompt2";
 const CONTEXT_ON_RUN_COMMAND: &str = "on_run";
+const CONTEXT_OUTPUT_FORMAT: &str = "output";
+
+/// global flag, accepted by every command, that overrides the output format
+pub const OUTPUT_FLAG: &str = "output";
 
 /// Datastructure to hold a list of command configs for shell use
 pub type CommandSet = HashMap<String, command::Config>;
 
+/// Arguments left over after taking out the global output flag, and the
+/// format that flag selected.
+type ExtractedArgs<'a> = (Vec<&'a str>, Option<OutputFormat>);
+
 /// Storage to pass information between commands.
 pub type Context = HashMap<String, String>;
 
 /// Contains state for entirety of cli interface
 pub struct Shell {
-    commands: CommandSet,
+    /// behind a lock because scripts can register commands at runtime
+    commands: RwLock<CommandSet>,
+    functions: RwLock<HashMap<String, Arc<Vec<script::Statement>>>>,
+    /// sorted so that listing them is stable
+    aliases: RwLock<BTreeMap<String, String>>,
+    macros: RwLock<BTreeMap<String, macros::Macro>>,
     help: String,
+    external: Option<ExternalCommands>,
+    rc_file: Option<PathBuf>,
+    /// set while the rc file runs, so that it doesn't save into itself
+    loading_rc: AtomicBool,
+    macro_file: Option<PathBuf>,
+    matching: MatchOptions,
 }
 
 impl Shell {
     pub fn new(commands: CommandSet, help: &str) -> Shell {
         Shell {
-            commands,
+            commands: RwLock::new(commands),
+            functions: RwLock::new(HashMap::new()),
+            aliases: RwLock::new(BTreeMap::new()),
+            macros: RwLock::new(BTreeMap::new()),
             help: help.into(),
+            external: None,
+            rc_file: None,
+            loading_rc: AtomicBool::new(false),
+            macro_file: None,
+            matching: MatchOptions::default(),
         }
     }
 
-    /// Given a command name, query the shell config to see if there is a
-    /// matching config. If there is, return a reference to it.
-    pub fn find_command_config(&self, command_name: &str) -> Option<&command::Config> {
-        self.commands.get(command_name)
+    /// Fall back to running external executables for command names that are
+    /// not in the CommandSet.
+    pub fn with_external_commands(mut self, external: ExternalCommands) -> Shell {
+        self.external = Some(external);
+        self
+    }
+
+    /// Run the statements in this file when the shell starts on stdin and
+    /// stdout (see load_rc). Aliases defined or removed while the shell runs
+    /// are saved back to it.
+    pub fn with_rc_file<P: Into<PathBuf>>(mut self, path: P) -> Shell {
+        self.rc_file = Some(path.into());
+        self
+    }
+
+    /// Load recorded macros from this file, and save them to it whenever a
+    /// recording stops.
+    pub fn with_macro_file<P: Into<PathBuf>>(mut self, path: P) -> Shell {
+        self.macro_file = Some(path.into());
+        if let Err(error) = self.load_macros() {
+            shell_println!("{}", error);
+        }
+        self
+    }
+
+    /// Match command and flag names ignoring case and/or after Unicode
+    /// normalization. Call this after every command has been added; it fails
+    /// if two of them can no longer be told apart.
+    pub fn with_matching(mut self, matching: MatchOptions) -> Result<Shell, NameCollisionError> {
+        matching.check_collisions(&self.commands.read().unwrap())?;
+        self.matching = matching;
+        Ok(self)
     }
 
     /// Given a command name, query the shell config to see if there is a
-    /// matching config. If there is, return a mutable reference to it.
-    pub fn find_command_config_mut(&mut self, command_name: &str) -> Option<&mut command::Config> {
-        self.commands.get_mut(command_name)
+    /// matching config. If there is, return a copy of it. An exact match wins
+    /// over one found through the shell's match options.
+    pub fn find_command_config(&self, command_name: &str) -> Option<command::Config> {
+        let commands = self.commands.read().unwrap();
+        if let Some(config) = commands.get(command_name) {
+            return Some(config.clone());
+        }
+        if self.matching.is_exact() {
+            return None;
+        }
+
+        let key = self.matching.command_key(command_name);
+        commands
+            .values()
+            .find(|c| self.matching.command_key(c.name()) == key)
+            .cloned()
+    }
+
+    /// Add a command to the shell while it is running. A command with the
+    /// same name is replaced and returned. It fails if the name, or one of
+    /// its long flags, can't be told apart from another under the shell's
+    /// match options.
+    pub fn register_command(
+        &self,
+        config: command::Config,
+    ) -> Result<Option<command::Config>, NameCollisionError> {
+        let mut commands = self.commands.write().unwrap();
+        self.matching.check_new_command(&commands, &config)?;
+        Ok(commands.insert(config.name().to_owned(), config))
+    }
+
+    /// Remove a command from the shell, returning its config
+    pub fn unregister_command(&self, command_name: &str) -> Option<command::Config> {
+        self.functions.write().unwrap().remove(command_name);
+        self.commands.write().unwrap().remove(command_name)
+    }
+
+    /// a snapshot of all commands registered with this shell
+    pub fn commands(&self) -> CommandSet {
+        self.commands.read().unwrap().clone()
+    }
+
+    /// the description this shell was created with
+    pub fn description(&self) -> &str {
+        &self.help
     }
 
     /// print help function for this Shell
@@ -50,9 +170,10 @@ impl Shell {
         let mut name_width = 0;
         let mut help_width = 0;
 
-        let _tmp: Vec<()> = self
-            .commands
+        let commands = self.commands.read().unwrap();
+        let _tmp: Vec<()> = commands
             .iter()
+            .filter(|e| !e.1.is_hidden())
             .map(|e| {
                 name_width = std::cmp::max(name_width, e.1.name().len() + 1);
                 help_width = std::cmp::max(help_width, e.1.help().len() + 1);
@@ -60,20 +181,14 @@ impl Shell {
             .collect();
 
         // do this to avoid having to pull in a formatting crate
-        for (_, c) in self.commands.iter() {
-            for idx in 0..name_width {
-                if idx < name_width - c.name().len() {
-                    write!(help_str, "{}", " ").unwrap();
-                } else {
-                    break;
-                }
-            }
-            write!(help_str, "{}    {}", c.name(), c.help()).unwrap();
-
-            for _ in 0..(help_width - c.help().len()) {
-                write!(help_str, "{}", " ").unwrap();
-            }
-            write!(help_str, "\n").unwrap();
+        for (_, c) in commands.iter().filter(|e| !e.1.is_hidden()) {
+            writeln!(
+                help_str,
+                "{}    {}",
+                output::pad_left(c.name(), name_width),
+                output::pad_right(c.help(), help_width),
+            )
+            .unwrap();
         }
 
         help_str
@@ -81,104 +196,247 @@ impl Shell {
 
     pub fn quit(&self) {
         // any "on_quit" actions should be run here
-        println!("Goodbye.\n");
+        shell_println!("Goodbye.\n");
     }
 
-    /// run the shell
+    /// run the rc file, then the shell on stdin and stdout
     pub fn run(&self, context: &mut Context) {
+        let mut session = session::stdio();
+        {
+            let _session = session.enter();
+            self.load_rc(context);
+        }
+        self.run_session(&mut session, context);
+    }
+
+    /// Run the statements in the rc file, if there is one. Aliases are not
+    /// saved while it runs. A served shell runs it once on the Context its
+    /// clients start with, rather than once for every client.
+    pub fn load_rc(&self, context: &mut Context) {
+        let path = match self.rc_file {
+            Some(ref path) => path,
+            None => return,
+        };
+        // a missing rc file is fine, it is created once something is saved
+        if let Ok(text) = fs::read_to_string(path) {
+            self.loading_rc.store(true, Ordering::SeqCst);
+            let result = self.execute(&text, context);
+            self.loading_rc.store(false, Ordering::SeqCst);
+            if let Err(error) = result {
+                shell_println!("{}", error);
+            }
+        }
+    }
+
+    /// Run the shell for one session until it quits, its input ends or it
+    /// is idle for longer than the read timeout of its reader.
+    pub fn run_session<R: BufRead>(&self, session: &mut Session<R>, context: &mut Context) {
+        let mut session = session.enter();
+
         let on_run_command = context
             .get(CONTEXT_ON_RUN_COMMAND)
             .unwrap_or(&String::from(""))
             .clone();
 
-        match self.run_parsed_result(&on_run_command, context) {
+        match self.execute(&on_run_command, context) {
             Ok(code) => {
                 if let command::ReturnCode::Abort = code {
                     return;
                 }
             }
             Err(error) => {
-                println!("{}", error);
+                shell_println!("{}", error);
             }
         }
 
+        // lines are collected here until they form complete statements, so
+        // that a block, a quoted string or a line ending in '\' can be spread
+        // over several lines. Syntax error positions count lines from the
+        // first line collected.
+        let mut input = String::new();
+
         'run: loop {
-            print!("{} ", self.make_shell_prompt(&(*context)));
-            io::stdout().flush().unwrap();
-
-            let mut input = String::new();
-            io::stdin()
-                .read_line(&mut input)
-                .expect("failed to read line");
-            let input = input.trim();
-
-            match self.run_parsed_result(input, context) {
-                Ok(code) => {
-                    if let command::ReturnCode::Abort = code {
-                        self.quit();
-                        break 'run;
-                    }
+            if input.is_empty() {
+                shell_print!("{} ", self.make_shell_prompt(&(*context)));
+            } else {
+                shell_print!("{} ", prompt::make_continuation_prompt(context));
+            }
+            session::flush_output();
+
+            let bytes_read = match session.read_line(&mut input) {
+                Ok(bytes_read) => bytes_read,
+                Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    shell_println!("\nidle timeout");
+                    self.quit();
+                    break 'run;
                 }
-                Err(error) => {
-                    println!("{}", error);
+                // the other side is gone, there is nobody left to tell
+                Err(_) => break 'run,
+            };
+            if bytes_read == 0 {
+                // end of input, e.g. ctrl-d or the end of a piped script
+                if !input.is_empty() {
+                    if let Err(error) = self.execute(&input, context) {
+                        shell_println!("\n{}", error);
+                    }
                 }
+                self.quit();
+                break 'run;
+            }
+
+            // checked before running, so that `record start` is not part of
+            // the macro and `record stop` has already ended it
+            let recording = self.is_recording();
+            let result = self.execute(&input, context);
+            let code = match result {
+                Ok(code) => code,
+                Err(error) => match error.downcast_ref::<SyntaxError>() {
+                    Some(syntax_error) if syntax_error.incomplete => continue 'run,
+                    _ => {
+                        shell_println!("{}", error);
+                        command::ReturnCode::Failure(1)
+                    }
+                },
+            };
+
+            let line = input.trim_end_matches('\n');
+            if !line.trim().is_empty() {
+                session::add_history(line);
+            }
+            if recording {
+                self.record_line(&input, &code);
             }
+            if let command::ReturnCode::Abort = code {
+                self.quit();
+                break 'run;
+            }
+            input.clear();
         }
+        session::flush_output();
+    }
+
+    /// whether the rc file is running
+    pub fn is_loading_rc(&self) -> bool {
+        self.loading_rc.load(Ordering::SeqCst)
     }
 
     /// generate prompt string
     fn make_shell_prompt(&self, context: &Context) -> String {
-        let mut prompt_string = String::from(DEFAULT_PROMPT);
-        if let Some(s) = context.get(CONTEXT_PROMPT_STRING) {
-            prompt_string = s.clone();
-        }
-
-        format!("{}>", prompt_string).into()
+        prompt::make_prompt(context)
     }
 
-    /// Given a user entered command string, extract the command name (which is
-    /// going to be the first argument separated by whitespace).
-    fn extract_command_name<'a>(&self, input_text: &'a str) -> Option<&'a str> {
-        input_text.split_whitespace().next()
+    /// Run a command given as a list of already separated tokens, the first
+    /// of which is the command name. This is what one-shot mode uses to run
+    /// the process arguments through the same CommandSet as the interactive
+    /// shell, e.g. `cli add 1 2 --modulo 5`.
+    pub fn run_args<S: AsRef<str>>(
+        &self,
+        args: &[S],
+        context: &mut Context,
+    ) -> Result<command::ReturnCode, Box<dyn Error>> {
+        let (args, format) = self.extract_output_format(args)?;
+        let command_name = match args.first() {
+            Some(name) => *name,
+            // nothing to run, e.g. the user hit "enter" on an empty line
+            None => return Ok(command::ReturnCode::Ok),
+        };
+
+        let code = match self.find_command_config(command_name) {
+            Some(config) => parse_tokens_with(&args[1..], &config, &self.matching)?.execute(self, context)?,
+            None => match self.run_external(command_name, &args[1..]) {
+                Some(result) => result?,
+                None => return Err(Box::new(UnknownCommandError(command_name.into()))),
+            },
+        };
+
+        if let command::ReturnCode::Data(ref data) = code {
+            let format = match format {
+                Some(format) => format,
+                None => self.output_format(context)?,
+            };
+            shell_print!("{}", data.render(format));
+        }
+        Ok(code)
     }
 
-    /// go from user input string to Command
-    fn parse_user_input<'a>(
-        &'a self,
-        input_text: &'a str,
-    ) -> Result<Option<Command<'a>>, Box<dyn Error>> {
-        let command_name = self.extract_command_name(input_text);
-        if command_name.is_none() {
-            // what seems to have happened here is that the user hit "enter"
-            // and didn't type in anything, so we received an empty string.
-            // This is not a bug, just ignore and redisplay the prompt.
-            return Ok(None);
+    /// The output format used to render structured command results, taken
+    /// from the "output" context variable (text, table or json).
+    pub fn output_format(&self, context: &Context) -> Result<OutputFormat, Box<dyn Error>> {
+        match context.get(CONTEXT_OUTPUT_FORMAT) {
+            Some(format) => Ok(format.parse()?),
+            None => Ok(OutputFormat::default()),
         }
-        let command_name = command_name.unwrap();
+    }
 
-        let command_config = self.find_command_config(command_name);
-        if command_config.is_none() {
-            return Err(Box::new(UnknownCommandError(command_name.into())));
+    /// Remove the global "--output <format>" (or "--output=<format>") flag
+    /// from the arguments. It is left alone after "--", for commands that
+    /// define an "output" flag of their own and for commands that are not
+    /// registered, such as external plugins, which get it as an argument.
+    fn extract_output_format<'s, S: AsRef<str>>(
+        &self,
+        args: &'s [S],
+    ) -> Result<ExtractedArgs<'s>, Box<dyn Error>> {
+        let global = format!("--{}", OUTPUT_FLAG);
+        let mut remaining = Vec::with_capacity(args.len());
+        let mut format = None;
+        let mut tokens = args.iter().map(|a| a.as_ref());
+
+        while let Some(token) = tokens.next() {
+            let value = if token == global {
+                Some(tokens.next().ok_or(FlagMissingArgError(flag::FlagQuery::Name(OUTPUT_FLAG.into())))?)
+            } else {
+                token.strip_prefix(&global).and_then(|rest| rest.strip_prefix('='))
+            };
+
+            match value {
+                Some(value) if !self.keeps_output_flag(remaining.first()) => {
+                    format = Some(value.parse()?);
+                }
+                _ if token == global => {
+                    // the command takes --output itself, hand it back
+                    remaining.push(token);
+                    remaining.push(value.unwrap());
+                }
+                _ if flag::is_end_of_flags(token) => {
+                    remaining.push(token);
+                    remaining.extend(tokens.by_ref());
+                }
+                _ => remaining.push(token),
+            }
         }
 
-        parse(input_text, command_config.unwrap())
+        Ok((remaining, format))
     }
 
-    /// parse a user input string and run the resulting command or show error.
-    /// This does parse_user_input() and then command.execute().
-    fn run_parsed_result<'a>(
-        &self,
-        input_text: &str,
-        context: &mut Context,
-    ) -> Result<command::ReturnCode, Box<dyn Error>> {
-        match self.parse_user_input(input_text) {
-            Ok(c_opt) => match c_opt {
-                Some(command) => command.execute(&self, context),
-                None => Ok(command::ReturnCode::Ok),
-            },
-            Err(error) => Err(error),
+    fn keeps_output_flag(&self, command_name: Option<&&str>) -> bool {
+        let name = match command_name {
+            Some(name) => name,
+            // a global flag given before the command name
+            None => return false,
+        };
+        match self.find_command_config(name) {
+            Some(c) => flag::query_flag_spec(&flag::FlagQuery::Name(OUTPUT_FLAG.into()), c.get_flags()).is_some(),
+            None => true,
         }
     }
+
+    /// If the command name resolves to an external executable, run it. Every
+    /// token after the name is passed on as an operand, since the executable
+    /// is responsible for its own flags.
+    fn run_external<S: AsRef<str>>(
+        &self,
+        command_name: &str,
+        args: &[S],
+    ) -> Option<Result<command::ReturnCode, Box<dyn Error>>> {
+        let path = self.external.as_ref()?.find(command_name)?;
+        let operands: OperandList = args
+            .iter()
+            .enumerate()
+            .map(|(idx, a)| Operand::at(a.as_ref(), idx + 1))
+            .collect();
+
+        Some(self.external.as_ref()?.run(&path, &operands))
+    }
 }
 
 /// Take a string that is presumably a valid cli command and turn it into
@@ -191,15 +449,51 @@ pub fn parse<'a>(
         return Ok(None);
     }
 
-    let mut tokens = input_text.split_whitespace().skip(1).peekable();
+    let tokens: Vec<&str> = input_text.split_whitespace().skip(1).collect();
+    parse_tokens(&tokens, config).map(Some)
+}
+
+/// Turn the tokens following a command name into a Command, sorting them
+/// into flags and operands according to the config.
+pub fn parse_tokens<'a, S: AsRef<str>>(
+    tokens: &[S],
+    config: &'a command::Config,
+) -> Result<Command<'a>, Box<dyn Error>> {
+    parse_tokens_with(tokens, config, &MatchOptions::default())
+}
+
+/// Like parse_tokens, but long flags are looked up with the match options
+/// when there is no exact match.
+pub fn parse_tokens_with<'a, S: AsRef<str>>(
+    tokens: &[S],
+    config: &'a command::Config,
+    matching: &MatchOptions,
+) -> Result<Command<'a>, Box<dyn Error>> {
+    let mut tokens = tokens.iter().map(|t| t.as_ref()).peekable();
     let mut command = Command::new(config, FlagSet::new(), OperandList::new());
 
     while tokens.peek().is_some() {
         let token = tokens.next().unwrap();
 
-        if flag::is_flag(&token) {
-            let flag_id = flag::extract_flag(&token).unwrap();
-            let spec = flag::query_flag_spec(&flag_id, config.get_flags());
+        let rest_are_operands = config.is_passthrough() && !command.operands().is_empty();
+        if rest_are_operands || flag::is_end_of_flags(token) {
+            if rest_are_operands {
+                let position = command.operands().len() + 1;
+                command.operands_mut().push(Operand::at(token, position));
+            }
+            for operand in tokens.by_ref() {
+                let position = command.operands().len() + 1;
+                command.operands_mut().push(Operand::at(operand, position));
+            }
+        } else if flag::is_flag(token) {
+            let flag_id = flag::extract_flag(token).unwrap();
+            let spec = flag::query_flag_spec(&flag_id, config.get_flags()).or_else(|| match flag_id {
+                FlagQuery::Name(ref name) if !matching.is_exact() => {
+                    let key = matching.flag_key(name);
+                    config.get_flags().iter().find(|s| matching.flag_key(s.name()) == key)
+                }
+                _ => None,
+            });
             if spec.is_none() {
                 return Err(Box::new(UnknownFlagError(flag_id)));
             }
@@ -210,9 +504,10 @@ pub fn parse<'a>(
             let parsed_arg = match spec.get_arg_spec() {
                 flag::ArgSpec::Optional => {
                     if next.is_none() || flag::is_flag(next.unwrap()) {
-                        continue;
+                        flag::Arg::Optional(None)
+                    } else {
+                        flag::Arg::Optional(Some(tokens.next().unwrap().to_string()))
                     }
-                    flag::Arg::Optional(Some(tokens.next().unwrap().to_string()))
                 }
                 flag::ArgSpec::Required => {
                     if next.is_none() || flag::is_flag(next.unwrap()) {
@@ -227,13 +522,14 @@ pub fn parse<'a>(
             // later value should overwrite an earlier one
             command
                 .flags_mut()
-                .replace(Flag::<'a>::new(&spec, parsed_arg));
+                .replace(Flag::<'a>::new(spec, parsed_arg));
         } else {
-            command.operands_mut().push(Operand::new(token));
+            let position = command.operands().len() + 1;
+            command.operands_mut().push(Operand::at(token, position));
         }
     }
 
-    Ok(Some(command))
+    Ok(command)
 }
 
 #[derive(Debug)]
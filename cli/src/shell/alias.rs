@@ -0,0 +1,371 @@
+//! Command aliases, e.g. `alias ll="list --long"`.
+//!
+//! An alias replaces the command name of a command line before it is parsed.
+//! The alias value may refer to the operands of the command line it replaces
+//! with $1, $2, ..., $# and $@; if it does not, the operands are appended to
+//! the value. Alias values may themselves start with an alias, but an alias
+//! is never expanded twice for the same command line, so `alias ls="ls -a"`
+//! works and alias loops end.
+use super::lexer::{self, TokenKind};
+use super::{Context, Shell};
+use crate::command::flag::FlagSpecSet;
+use crate::command::output::{Output, Table};
+use crate::command::{self, Command, ReturnCode};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+/// start of the rc file lines that define aliases
+const RC_ALIAS_PREFIX: &str = "alias ";
+
+#[derive(Debug)]
+pub struct AliasError(pub String, pub String);
+
+impl fmt::Display for AliasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error: alias {}: {}", self.0, self.1)
+    }
+}
+
+impl Error for AliasError {}
+
+impl Shell {
+    /// the value of an alias, if it is defined
+    pub fn alias(&self, name: &str) -> Option<String> {
+        self.aliases.read().unwrap().get(name).cloned()
+    }
+
+    /// all aliases as (name, value) pairs, sorted by name
+    pub fn aliases(&self) -> Vec<(String, String)> {
+        self.aliases
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(n, v)| (n.clone(), v.clone()))
+            .collect()
+    }
+
+    /// Define or replace an alias
+    pub fn set_alias(&self, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let valid_name = !name.is_empty()
+            && !name.starts_with('-')
+            && !name
+                .chars()
+                .any(|c| c.is_whitespace() || "=$'\"\\;{}".contains(c));
+        if !valid_name {
+            return Err(Box::new(AliasError(
+                name.into(),
+                "invalid alias name".into(),
+            )));
+        }
+
+        // make sure the value can be expanded before accepting it
+        single_command(name, value)?;
+
+        self.aliases
+            .write()
+            .unwrap()
+            .insert(name.into(), value.into());
+        Ok(())
+    }
+
+    /// Remove an alias, returning its value
+    pub fn remove_alias(&self, name: &str) -> Option<String> {
+        self.aliases.write().unwrap().remove(name)
+    }
+
+    /// Replace a leading alias in args with its value, repeatedly, until the
+    /// command name is not an alias or was already expanded once.
+    pub fn expand_aliases(
+        &self,
+        mut args: Vec<String>,
+        context: &Context,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut expanded = HashSet::new();
+
+        while let Some(name) = args.first().cloned() {
+            let value = match self.alias(&name) {
+                Some(value) if !expanded.contains(&name) => value,
+                _ => break,
+            };
+            args = substitute(&name, &value, &args[1..], context)?;
+            expanded.insert(name);
+        }
+
+        Ok(args)
+    }
+
+    /// Write the current aliases into the rc file. An alias line that is
+    /// already there is updated in place, or removed with its alias, new
+    /// aliases are added at the end and every other line is left alone.
+    /// Nothing is saved while the rc file itself is running.
+    pub fn save_aliases(&self) -> Result<(), Box<dyn Error>> {
+        let path = match self.rc_file {
+            Some(ref path) if !self.is_loading_rc() => path,
+            _ => return Ok(()),
+        };
+
+        let existing = fs::read_to_string(path).unwrap_or_default();
+        let aliases = self.aliases.read().unwrap().clone();
+        let contents = update_rc_aliases(&existing, &aliases);
+        if contents != existing {
+            fs::write(path, contents)?;
+        }
+        Ok(())
+    }
+}
+
+/// The rc file text with its alias lines brought up to date
+fn update_rc_aliases(rc: &str, aliases: &BTreeMap<String, String>) -> String {
+    let mut written = HashSet::new();
+    let mut contents = String::with_capacity(rc.len());
+
+    for line in rc.lines() {
+        let (name, value) = match rc_alias(line) {
+            Some(alias) => alias,
+            None => {
+                contents.push_str(line);
+                contents.push('\n');
+                continue;
+            }
+        };
+        // lines of removed aliases, and of aliases defined again further
+        // down, are dropped
+        if written.contains(&name) {
+            continue;
+        }
+        match aliases.get(&name) {
+            // a line that still defines the alias as it is keeps its quoting
+            Some(current) if *current == value => {
+                contents.push_str(line);
+                contents.push('\n');
+            }
+            Some(current) => contents.push_str(&rc_alias_line(&name, current)),
+            None => continue,
+        }
+        written.insert(name);
+    }
+
+    for (name, value) in aliases.iter().filter(|(name, _)| !written.contains(*name)) {
+        contents.push_str(&rc_alias_line(name, value));
+    }
+    contents
+}
+
+/// The name and value of an rc file line of the form `alias name=value`
+fn rc_alias(line: &str) -> Option<(String, String)> {
+    let words: Vec<lexer::Word> = lexer::tokenize(line)
+        .ok()?
+        .into_iter()
+        .map(|token| match token.kind {
+            TokenKind::Word(word) => Some(word),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+
+    match words.as_slice() {
+        [alias, definition] if alias.is_bare(RC_ALIAS_PREFIX.trim_end()) => {
+            let definition = definition.expand(&Context::new());
+            let (name, value) = definition.split_once('=')?;
+            Some((name.into(), value.into()))
+        }
+        _ => None,
+    }
+}
+
+fn rc_alias_line(name: &str, value: &str) -> String {
+    format!("{}{}={}\n", RC_ALIAS_PREFIX, name, quote(value))
+}
+
+/// Tokenize an alias value, which must be a single plain command
+fn single_command(name: &str, value: &str) -> Result<Vec<lexer::Word>, Box<dyn Error>> {
+    let mut words = Vec::new();
+    for token in lexer::tokenize(value)? {
+        match token.kind {
+            TokenKind::Word(word) => words.push(word),
+            _ => {
+                return Err(Box::new(AliasError(
+                    name.into(),
+                    "the value must be a single command".into(),
+                )))
+            }
+        }
+    }
+    if words.is_empty() {
+        return Err(Box::new(AliasError(
+            name.into(),
+            "the value is empty".into(),
+        )));
+    }
+    Ok(words)
+}
+
+/// Expand the alias value with args bound to the placeholders
+fn substitute(
+    name: &str,
+    value: &str,
+    args: &[String],
+    context: &Context,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let words = single_command(name, value)?;
+
+    let mut bound = context.clone();
+    for (n, arg) in args.iter().enumerate() {
+        bound.insert((n + 1).to_string(), arg.clone());
+    }
+    bound.insert(lexer::ARG_COUNT_VARIABLE.into(), args.len().to_string());
+    bound.insert(lexer::ALL_ARGS_VARIABLE.into(), args.join(" "));
+
+    let mut expanded: Vec<String> = words.iter().flat_map(|w| w.expand_args(&bound)).collect();
+    if !has_placeholders(value) {
+        expanded.extend(args.iter().cloned());
+    }
+    Ok(expanded)
+}
+
+/// check for $1..$9, $# or $@ in an alias value
+fn has_placeholders(value: &str) -> bool {
+    value
+        .as_bytes()
+        .windows(2)
+        .any(|w| w[0] == b'$' && (w[1].is_ascii_digit() || w[1] == b'@' || w[1] == b'#'))
+}
+
+/// single quote text so the lexer reads it back unchanged
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// The `alias` builtin. Without operands it lists every alias, with
+/// `name=value` operands it defines aliases and with `name` operands it shows
+/// those aliases.
+pub fn alias_config() -> command::Config {
+    command::Config::new(
+        "alias",
+        FlagSpecSet::new(),
+        "Define or list aliases, e.g. alias ll=\"list --long\"",
+        |command: &Command,
+         shell: &Shell,
+         _context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            let mut listed = Vec::new();
+            let mut changed = false;
+
+            for operand in command.operands() {
+                match operand.value().split_once('=') {
+                    Some((name, value)) => {
+                        shell.set_alias(name, value)?;
+                        changed = true;
+                    }
+                    None => match shell.alias(operand.value()) {
+                        Some(value) => listed.push((operand.value().to_string(), value)),
+                        None => {
+                            return Err(Box::new(AliasError(
+                                operand.value().into(),
+                                "not found".into(),
+                            )))
+                        }
+                    },
+                }
+            }
+
+            if changed {
+                shell.save_aliases()?;
+            }
+            if command.operands().is_empty() {
+                listed = shell.aliases();
+            } else if listed.is_empty() {
+                return Ok(ReturnCode::Ok);
+            }
+
+            let mut table = Table::new(&["name", "value"]);
+            for (name, value) in listed {
+                table.push_row(vec![name.into(), value.into()]);
+            }
+            Ok(ReturnCode::Data(Output::Table(table)))
+        },
+    )
+}
+
+/// The `unalias` builtin, which removes the named aliases
+pub fn unalias_config() -> command::Config {
+    command::Config::new(
+        "unalias",
+        FlagSpecSet::new(),
+        "Remove aliases",
+        |command: &Command,
+         shell: &Shell,
+         _context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            for operand in command.operands() {
+                if shell.remove_alias(operand.value()).is_none() {
+                    return Err(Box::new(AliasError(
+                        operand.value().into(),
+                        "not found".into(),
+                    )));
+                }
+            }
+            shell.save_aliases()?;
+            Ok(ReturnCode::Ok)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::CommandSet;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn expansion() {
+        let shell = Shell::new(CommandSet::new(), "");
+        shell.set_alias("ll", "list --long").unwrap();
+        shell.set_alias("l", "ll -a").unwrap();
+        shell.set_alias("ls", "ls --color").unwrap();
+        shell.set_alias("swap", "mv $2 $1").unwrap();
+        shell.set_alias("each", "run \"$@\" $@").unwrap();
+        shell.set_alias("ping", "pong").unwrap();
+        shell.set_alias("pong", "ping").unwrap();
+
+        let context = Context::new();
+        let expand = |text: &str| shell.expand_aliases(args(text), &context).unwrap();
+
+        assert_eq!(args("list --long a b"), expand("ll a b"));
+        assert_eq!(args("list --long -a x"), expand("l x"));
+        assert_eq!(args("ls --color"), expand("ls"));
+        assert_eq!(args("mv b a"), expand("swap a b"));
+        assert_eq!(vec!["run", "x y", "x", "y"], expand("each x y"));
+        assert_eq!(args("ping"), expand("ping"));
+    }
+
+    #[test]
+    fn rc_file() {
+        let aliases: BTreeMap<String, String> = [("ll", "list --long"), ("new", "x $1")]
+            .into_iter()
+            .map(|(n, v)| (n.into(), v.into()))
+            .collect();
+        let rc = "alias ll=\"list --long\"\nprompt %?\nalias gone=x\nalias new=y\nalias ll=z\n";
+
+        assert_eq!(
+            "alias ll=\"list --long\"\nprompt %?\nalias new='x $1'\n",
+            update_rc_aliases(rc, &aliases)
+        );
+        assert_eq!(
+            "alias ll='list --long'\nalias new='x $1'\n",
+            update_rc_aliases("", &aliases)
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let shell = Shell::new(CommandSet::new(), "");
+        assert!(shell.set_alias("a b", "x").is_err());
+        assert!(shell.set_alias("x", "a; b").is_err());
+        assert!(shell.set_alias("x", "").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use super::session;
+use crate::command::operand::OperandList;
+use crate::command::ReturnCode;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+
+/// default prefix of executables that are picked up as commands
+const DEFAULT_PREFIX: &str = "cli-";
+
+#[derive(Debug)]
+pub struct ExternalCommandError(pub PathBuf, pub String);
+
+impl fmt::Display for ExternalCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Error: external command {}: {}",
+            self.0.display(),
+            self.1
+        )
+    }
+}
+
+impl Error for ExternalCommandError {}
+
+/// Fallback for command names that are not in the shell's CommandSet. A name
+/// "foo" is resolved to an executable "cli-foo", searched for in the plugin
+/// directories first (in the order they were added) and then on PATH, or in
+/// the directories given with path_dirs.
+#[derive(Clone, Debug)]
+pub struct ExternalCommands {
+    prefix: String,
+    plugin_dirs: Vec<PathBuf>,
+    search_path: bool,
+    /// searched instead of PATH when set
+    path_dirs: Option<Vec<PathBuf>>,
+}
+
+impl ExternalCommands {
+    pub fn new() -> ExternalCommands {
+        ExternalCommands {
+            prefix: DEFAULT_PREFIX.into(),
+            plugin_dirs: Vec::new(),
+            search_path: true,
+            path_dirs: None,
+        }
+    }
+
+    /// change the executable name prefix (default "cli-")
+    pub fn prefix(mut self, prefix: &str) -> ExternalCommands {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// add a directory to search for plugins before PATH
+    pub fn plugin_dir<P: AsRef<Path>>(mut self, dir: P) -> ExternalCommands {
+        self.plugin_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// enable or disable searching the PATH environment variable, or the
+    /// directories given with path_dirs
+    pub fn search_path(mut self, enabled: bool) -> ExternalCommands {
+        self.search_path = enabled;
+        self
+    }
+
+    /// search these directories after the plugin directories instead of
+    /// the ones in the PATH environment variable
+    pub fn path_dirs<I, P>(mut self, dirs: I) -> ExternalCommands
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.path_dirs = Some(dirs.into_iter().map(|d| d.as_ref().to_path_buf()).collect());
+        self
+    }
+
+    /// Given a command name, return the path of the executable that
+    /// implements it, if there is one.
+    pub fn find(&self, command_name: &str) -> Option<PathBuf> {
+        // do not let a command name walk out of the search directories
+        if command_name.is_empty() || command_name.contains(['/', '\\']) {
+            return None;
+        }
+        let file_name = format!("{}{}", self.prefix, command_name);
+
+        let path_dirs = match (self.search_path, &self.path_dirs) {
+            (false, _) => Vec::new(),
+            (true, Some(dirs)) => dirs.clone(),
+            (true, None) => match env::var_os("PATH") {
+                Some(path) => env::split_paths(&path).collect(),
+                None => Vec::new(),
+            },
+        };
+
+        self.plugin_dirs
+            .iter()
+            .chain(path_dirs.iter())
+            .map(|dir| dir.join(&file_name))
+            .find(|candidate| is_executable(candidate))
+    }
+
+    /// Run the executable with the operands as its arguments. Its stdout is
+    /// forwarded to the output of the current session and its stderr to the
+    /// session's error output as it runs. It shares stdin only with a session
+    /// on the process's stdin. The exit status becomes ReturnCode::Ok on
+    /// success or ReturnCode::Failure otherwise.
+    pub fn run(&self, path: &Path, operands: &OperandList) -> Result<ReturnCode, Box<dyn Error>> {
+        let error = |e: io::Error| ExternalCommandError(path.into(), e.to_string());
+        // a served session must not hand the server's stdin to the plugin
+        let stdin = match session::reads_stdin() {
+            true => process::Stdio::inherit(),
+            false => process::Stdio::null(),
+        };
+        let mut child = process::Command::new(path)
+            .args(operands.iter().map(|o| o.value()))
+            .stdin(stdin)
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .map_err(error)?;
+
+        // the session's writers belong to this thread, so the pipes are read
+        // on their own threads and what they read is written here
+        let (tx, rx) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            forward(stdout, Pipe::Stdout, tx.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            forward(stderr, Pipe::Stderr, tx);
+        }
+
+        // keep draining after a failed write, so the plugin doesn't block
+        let mut written = Ok(());
+        for (pipe, bytes) in rx {
+            if written.is_ok() {
+                written = match pipe {
+                    Pipe::Stdout => session::write_output_bytes(&bytes),
+                    Pipe::Stderr => session::write_error_bytes(&bytes),
+                };
+                session::flush_output();
+            }
+        }
+        let status = child.wait().map_err(error)?;
+        written?;
+
+        match status.code() {
+            Some(0) => Ok(ReturnCode::Ok),
+            Some(code) => Ok(ReturnCode::Failure(code)),
+            None => Err(Box::new(ExternalCommandError(
+                path.into(),
+                "terminated by signal".into(),
+            ))),
+        }
+    }
+}
+
+impl Default for ExternalCommands {
+    fn default() -> Self {
+        ExternalCommands::new()
+    }
+}
+
+/// which output of an external command a chunk was read from
+#[derive(Clone, Copy)]
+enum Pipe {
+    Stdout,
+    Stderr,
+}
+
+/// Send what is read from a pipe over tx until the pipe is closed
+fn forward<R: Read + Send + 'static>(mut reader: R, pipe: Pipe, tx: mpsc::Sender<(Pipe, Vec<u8>)>) {
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        loop {
+            let read = match reader.read(&mut buf) {
+                Ok(0) => return,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return,
+            };
+            if tx.send((pipe, buf[..read].to_vec())).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::command::operand::Operand;
+    use crate::shell::session::tests::Buffer;
+    use crate::shell::Session;
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// write an executable shell script cli-<name> into dir
+    fn plugin(dir: &Path, name: &str, script: &str) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join(format!("{}{}", DEFAULT_PREFIX, name));
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn find() {
+        let dir = env::temp_dir().join(format!("cli-external-find-{}", process::id()));
+        let (plugins, bin) = (dir.join("plugins"), dir.join("bin"));
+        let both = plugin(&plugins, "both", "exit 0");
+        plugin(&bin, "both", "exit 0");
+        let on_path = plugin(&bin, "path", "exit 0");
+        fs::write(bin.join("cli-plain"), "not executable").unwrap();
+
+        let external = ExternalCommands::new()
+            .plugin_dir(&plugins)
+            .path_dirs([&bin]);
+        let found = (
+            external.find("both"),
+            external.find("path"),
+            external.find("plain"),
+            external.find("missing"),
+            external.find("../bin/cli-path"),
+            external.clone().search_path(false).find("path"),
+        );
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(Some(both), found.0);
+        assert_eq!(Some(on_path), found.1);
+        assert_eq!(
+            (None, None, None, None),
+            (found.2, found.3, found.4, found.5)
+        );
+    }
+
+    #[test]
+    fn run() {
+        let dir = env::temp_dir().join(format!("cli-external-run-{}", process::id()));
+        let path = plugin(
+            &dir,
+            "echo",
+            "echo \"out $*\"\necho \"err $1\" >&2\nexit $2",
+        );
+        let operands = |args: &[&str]| {
+            args.iter()
+                .map(|a| Operand::new(a))
+                .collect::<OperandList>()
+        };
+
+        let (output, errors) = (Buffer::default(), Buffer::default());
+        let mut session = Session::new(io::empty(), output.clone()).errors(errors.clone());
+        // a session that doesn't read the process's stdin gives none
+        let reader = plugin(&dir, "read", "read line || echo \"no input\"");
+        let codes = {
+            let _guard = session.enter();
+            let external = ExternalCommands::new();
+            (
+                external.run(&path, &operands(&["a b", "0"])).unwrap(),
+                external.run(&path, &operands(&["c", "3"])).unwrap(),
+                external.run(&reader, &operands(&[])).unwrap(),
+            )
+        };
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(
+            (ReturnCode::Ok, ReturnCode::Failure(3), ReturnCode::Ok),
+            codes
+        );
+        assert_eq!("out a b 0\nout c 3\nno input\n", output.text());
+        assert_eq!("err a b\nerr c\n", errors.text());
+    }
+
+    #[test]
+    fn output_flag_reaches_plugins() {
+        let dir = env::temp_dir().join(format!("cli-external-output-{}", process::id()));
+        plugin(&dir, "args", "echo \"$*\"");
+        let shell = crate::shell::Shell::new(crate::shell::CommandSet::new(), "")
+            .with_external_commands(ExternalCommands::new().plugin_dir(&dir).search_path(false));
+
+        let output = Buffer::default();
+        let mut session = Session::new(io::empty(), output.clone());
+        let code = {
+            let _guard = session.enter();
+            let mut context = crate::shell::Context::new();
+            shell.run_args(&["args", "--output", "json", "--output=csv"], &mut context)
+        };
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(ReturnCode::Ok, code.unwrap());
+        assert_eq!("--output json --output=csv\n", output.text());
+    }
+}
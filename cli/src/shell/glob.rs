@@ -0,0 +1,286 @@
+//! Filename pattern matching. Supported wildcards:
+//!
+//! * `*` matches any run of characters within a path component
+//! * `?` matches exactly one character
+//! * `[abc]`, `[a-z]` match one character from the set or range, and
+//!   `[!abc]` (or `[^abc]`) one character that is not in it
+//! * `**` as a whole path component matches zero or more directories
+//!
+//! A backslash makes the following character literal. Names starting with a
+//! '.' are only matched when the pattern component itself starts with '.'.
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct NoMatchError(pub String);
+
+impl fmt::Display for NoMatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error: no files match {}", self.0)
+    }
+}
+
+impl Error for NoMatchError {}
+
+/// check whether text contains an unescaped wildcard
+pub fn has_wildcards(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+/// escape every wildcard in text so that it only matches itself
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// remove the escaping backslashes from a pattern
+pub fn unescape(pattern: &str) -> String {
+    let mut text = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+/// Match a single path component (no '/') against a pattern
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_chars(&pattern, &name)
+}
+
+/// Expand a pattern against the filesystem. Matches are sorted, and an empty
+/// list is returned when nothing matches.
+pub fn expand(pattern: &str) -> Vec<String> {
+    let (root, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (String::from("/"), rest),
+        None => (String::new(), pattern),
+    };
+    let components: Vec<&str> = rest.split('/').filter(|c| !c.is_empty()).collect();
+    if components.is_empty() {
+        return Vec::new();
+    }
+
+    let mut found = vec![root];
+    for (idx, component) in components.iter().enumerate() {
+        let last = idx + 1 == components.len();
+        let mut next = Vec::new();
+
+        for base in found.iter() {
+            if *component == "**" {
+                if !last {
+                    // zero directories
+                    next.push(base.clone());
+                }
+                walk(base, last, &mut next);
+            } else if has_wildcards(component) {
+                for name in list_dir(base) {
+                    let hidden_ok = !name.starts_with('.') || component.starts_with('.');
+                    let path = join(base, &name);
+                    if hidden_ok && matches(component, &name) && (last || Path::new(&path).is_dir())
+                    {
+                        next.push(path);
+                    }
+                }
+            } else {
+                let path = join(base, &unescape(component));
+                let path_ref = Path::new(&path);
+                if (last && path_ref.symlink_metadata().is_ok()) || path_ref.is_dir() {
+                    next.push(path);
+                }
+            }
+        }
+
+        found = next;
+        if found.is_empty() {
+            break;
+        }
+    }
+
+    found.sort();
+    found.dedup();
+    found
+}
+
+fn join(base: &str, name: &str) -> String {
+    if base.is_empty() {
+        name.into()
+    } else if base.ends_with('/') {
+        format!("{}{}", base, name)
+    } else {
+        format!("{}/{}", base, name)
+    }
+}
+
+/// sorted names of all entries in a directory, hidden ones included
+fn list_dir(base: &str) -> Vec<String> {
+    let dir = if base.is_empty() { "." } else { base };
+    let mut names: Vec<String> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    names
+}
+
+/// Collect everything below base, skipping hidden entries. Without
+/// include_files only directories are collected.
+fn walk(base: &str, include_files: bool, found: &mut Vec<String>) {
+    for name in list_dir(base) {
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = join(base, &name);
+        // do not follow symlinks, a link cycle would never end
+        let is_dir = Path::new(&path)
+            .symlink_metadata()
+            .map(|m| m.is_dir())
+            .unwrap_or(false);
+
+        if is_dir || include_files {
+            found.push(path.clone());
+        }
+        if is_dir {
+            walk(&path, include_files, found);
+        }
+    }
+}
+
+fn match_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => {
+            let rest = &pattern[1..];
+            if rest.first() == Some(&'*') {
+                return match_chars(rest, name);
+            }
+            (0..=name.len()).any(|skip| match_chars(rest, &name[skip..]))
+        }
+        Some('?') => !name.is_empty() && match_chars(&pattern[1..], &name[1..]),
+        Some('[') => match parse_class(pattern) {
+            Some((len, class)) => {
+                !name.is_empty()
+                    && class.contains(name[0])
+                    && match_chars(&pattern[len..], &name[1..])
+            }
+            // no closing bracket, treat it as a literal '['
+            None => name.first() == Some(&'[') && match_chars(&pattern[1..], &name[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && match_chars(&pattern[2..], &name[1..])
+        }
+        Some(c) => name.first() == Some(c) && match_chars(&pattern[1..], &name[1..]),
+    }
+}
+
+/// a bracket expression, e.g. [a-z_] or [!0-9]
+struct CharClass {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    fn contains(&self, c: char) -> bool {
+        self.ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi) != self.negated
+    }
+}
+
+/// Parse the bracket expression at the start of pattern. Returns the number
+/// of pattern characters it spans and the class.
+fn parse_class(pattern: &[char]) -> Option<(usize, CharClass)> {
+    let mut idx = 1;
+    let negated = matches!(pattern.get(idx), Some('!') | Some('^'));
+    if negated {
+        idx += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let mut c = *pattern.get(idx)?;
+        if c == ']' && !first {
+            return Some((idx + 1, CharClass { negated, ranges }));
+        }
+        if c == '\\' {
+            idx += 1;
+            c = *pattern.get(idx)?;
+        }
+        first = false;
+
+        match (pattern.get(idx + 1), pattern.get(idx + 2)) {
+            (Some('-'), Some(hi)) if *hi != ']' => {
+                ranges.push((c, *hi));
+                idx += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                idx += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rc"));
+        assert!(matches("?ain.*", "main.rs"));
+        assert!(matches("[lm]ain.rs", "main.rs"));
+        assert!(matches("[a-z]*", "main.rs"));
+        assert!(!matches("[!a-z]*", "main.rs"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("a\\*", "a*"));
+        assert!(!matches("a\\*", "ab"));
+        assert!(matches("[abc", "[abc"));
+    }
+
+    #[test]
+    fn escaping() {
+        assert!(has_wildcards("src/*.rs"));
+        assert!(!has_wildcards("src/\\*.rs"));
+        assert_eq!("a\\*b\\[c\\]", escape("a*b[c]"));
+        assert_eq!("a*b[c]", unescape(&escape("a*b[c]")));
+    }
+
+    #[test]
+    fn filesystem() {
+        let root = env!("CARGO_MANIFEST_DIR");
+        let found = expand(&format!("{}/src/shell/*.rs", root));
+        assert!(found.contains(&format!("{}/src/shell/glob.rs", root)));
+        assert!(found.windows(2).all(|w| w[0] < w[1]));
+
+        let recursive = expand(&format!("{}/src/**/glob.rs", root));
+        assert_eq!(vec![format!("{}/src/shell/glob.rs", root)], recursive);
+
+        assert!(expand(&format!("{}/src/*.nothing", root)).is_empty());
+    }
+}
@@ -0,0 +1,475 @@
+//! Split shell input into words and the punctuation used by scripts.
+//!
+//! * whitespace separates words, a newline or ';' separates statements and
+//!   '{' / '}' delimit blocks
+//! * text in single quotes is taken literally
+//! * text in double quotes keeps its whitespace, but $variables are expanded
+//!   and \" \\ \$ are escapes
+//! * outside of quotes, a backslash makes the next character literal
+//! * a '#' at the start of a word starts a comment that runs to the end of
+//!   the line
+use super::glob;
+use super::Context;
+use std::error::Error;
+use std::fmt;
+
+/// context variable that holds the status of the last command, also
+/// available as $?
+pub const STATUS_VARIABLE: &str = "status";
+/// context variable that holds the number of positional arguments
+pub const ARG_COUNT_VARIABLE: &str = "#";
+/// context variable that holds all positional arguments joined by spaces
+pub const ALL_ARGS_VARIABLE: &str = "@";
+
+#[derive(Debug, PartialEq)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    /// the input ended before the construct was finished, so it may become
+    /// valid once more input is added
+    pub incomplete: bool,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Error: syntax error at line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for SyntaxError {}
+
+/// piece of a word, which remembers how it was quoted
+#[derive(Clone, Debug, PartialEq)]
+pub enum Part {
+    /// unquoted text, subject to variable expansion and globbing
+    Bare(String),
+    /// double quoted text, subject to variable expansion only
+    Quoted(String),
+    /// single quoted or escaped text, used as is
+    Literal(String),
+}
+
+/// A single shell word, e.g. `"$dir"/*.txt` is made of three parts
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Word {
+    parts: Vec<Part>,
+}
+
+impl Word {
+    pub fn parts(&self) -> &[Part] {
+        &self.parts
+    }
+
+    /// check if this word is exactly the given unquoted text, e.g. a keyword
+    pub fn is_bare(&self, text: &str) -> bool {
+        matches!(self.parts.as_slice(), [Part::Bare(s)] if s == text)
+    }
+
+    /// the unquoted text of this word, if it has no quoted parts at all
+    pub fn as_bare(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [Part::Bare(s)] => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Expand variables and join the parts into the final text
+    pub fn expand(&self, context: &Context) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Bare(s) | Part::Quoted(s) => expand_variables(s, context),
+                Part::Literal(s) => s.clone(),
+            })
+            .collect()
+    }
+
+    /// Expand variables and, if the unquoted text typed in this word holds a
+    /// wildcard, return the glob pattern for it. Quoted wildcards and the
+    /// values of variables are escaped, so `$?` or a variable holding `*`
+    /// never make a word a pattern.
+    pub fn glob_pattern(&self, context: &Context) -> Option<String> {
+        let has_wildcards = self.parts.iter().any(|p| match p {
+            Part::Bare(s) => {
+                glob::has_wildcards(&expand_variables_with(s, &Context::new(), |_| {
+                    String::new()
+                }))
+            }
+            _ => false,
+        });
+        if !has_wildcards {
+            return None;
+        }
+
+        Some(
+            self.parts
+                .iter()
+                .map(|part| match part {
+                    Part::Bare(s) => expand_variables_with(s, context, glob::escape),
+                    Part::Quoted(s) => glob::escape(&expand_variables(s, context)),
+                    Part::Literal(s) => glob::escape(s),
+                })
+                .collect(),
+        )
+    }
+
+    /// Expand this word into the list of words it stands for. Usually this
+    /// is one word, but an unquoted $@ splits into every positional argument.
+    pub fn expand_args(&self, context: &Context) -> Vec<String> {
+        if self.is_bare("$@") {
+            return positional_args(context);
+        }
+        vec![self.expand(context)]
+    }
+
+    fn push(&mut self, part: Part) {
+        // merge with the previous part where possible to keep words simple
+        match (self.parts.last_mut(), part) {
+            (Some(Part::Bare(a)), Part::Bare(b)) => a.push_str(&b),
+            (Some(Part::Literal(a)), Part::Literal(b)) => a.push_str(&b),
+            (_, part) => self.parts.push(part),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Word(Word),
+    /// newline or ';'
+    Separator,
+    OpenBrace,
+    CloseBrace,
+}
+
+/// A token and the 1-based line and column where it starts
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Split input text into tokens
+pub fn tokenize(text: &str) -> Result<Vec<Token>, SyntaxError> {
+    Lexer::new(text).run()
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    idx: usize,
+    line: usize,
+    column: usize,
+    tokens: Vec<Token>,
+    word: Option<(Word, usize, usize)>,
+}
+
+impl Lexer {
+    fn new(text: &str) -> Lexer {
+        Lexer {
+            chars: text.chars().collect(),
+            idx: 0,
+            line: 1,
+            column: 1,
+            tokens: Vec::new(),
+            word: None,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.idx).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.idx += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, line: usize, column: usize, message: &str, incomplete: bool) -> SyntaxError {
+        SyntaxError {
+            line,
+            column,
+            message: message.into(),
+            incomplete,
+        }
+    }
+
+    /// add a part to the word being built, starting one if necessary
+    fn push_part(&mut self, part: Part, line: usize, column: usize) {
+        let (word, _, _) = self
+            .word
+            .get_or_insert_with(|| (Word::default(), line, column));
+        word.push(part);
+    }
+
+    fn finish_word(&mut self) {
+        if let Some((word, line, column)) = self.word.take() {
+            self.tokens.push(Token {
+                kind: TokenKind::Word(word),
+                line,
+                column,
+            });
+        }
+    }
+
+    fn punctuation(&mut self, kind: TokenKind) {
+        self.finish_word();
+        let (line, column) = (self.line, self.column);
+        self.advance();
+        self.tokens.push(Token { kind, line, column });
+    }
+
+    fn run(mut self) -> Result<Vec<Token>, SyntaxError> {
+        while let Some(c) = self.peek() {
+            let (line, column) = (self.line, self.column);
+            match c {
+                '\n' | ';' => self.punctuation(TokenKind::Separator),
+                '{' => self.punctuation(TokenKind::OpenBrace),
+                '}' => self.punctuation(TokenKind::CloseBrace),
+                c if c.is_whitespace() => {
+                    self.finish_word();
+                    self.advance();
+                }
+                '#' if self.word.is_none() => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.advance();
+                    }
+                }
+                '\'' => {
+                    self.advance();
+                    let mut text = String::new();
+                    loop {
+                        match self.advance() {
+                            Some('\'') => break,
+                            Some(c) => text.push(c),
+                            None => return Err(self.error(line, column, "unclosed '", true)),
+                        }
+                    }
+                    self.push_part(Part::Literal(text), line, column);
+                }
+                '"' => self.double_quoted(line, column)?,
+                '\\' => {
+                    self.advance();
+                    match self.advance() {
+                        // an escaped newline joins the two lines, so there
+                        // has to be a next line
+                        Some('\n') if self.peek().is_none() => {
+                            return Err(self.error(line, column, "line continues after '\\'", true))
+                        }
+                        Some('\n') => (),
+                        Some(c) => self.push_part(Part::Literal(c.to_string()), line, column),
+                        None => {
+                            return Err(self.error(
+                                line,
+                                column,
+                                "nothing to escape after '\\'",
+                                true,
+                            ))
+                        }
+                    }
+                }
+                '$' if self.chars.get(self.idx + 1) == Some(&'{') => {
+                    let mut text = String::new();
+                    loop {
+                        match self.advance() {
+                            Some('}') => break,
+                            Some(c) => text.push(c),
+                            None => return Err(self.error(line, column, "unclosed '${'", true)),
+                        }
+                    }
+                    text.push('}');
+                    self.push_part(Part::Bare(text), line, column);
+                }
+                c => {
+                    self.advance();
+                    self.push_part(Part::Bare(c.to_string()), line, column);
+                }
+            }
+        }
+
+        self.finish_word();
+        Ok(self.tokens)
+    }
+
+    fn double_quoted(&mut self, line: usize, column: usize) -> Result<(), SyntaxError> {
+        self.advance();
+        let mut text = String::new();
+        loop {
+            match self.advance() {
+                Some('"') => break,
+                Some('\\') => match self.peek() {
+                    Some('$') => {
+                        // an escaped '$' must survive variable expansion
+                        self.advance();
+                        self.push_part(Part::Quoted(std::mem::take(&mut text)), line, column);
+                        self.push_part(Part::Literal("$".into()), line, column);
+                    }
+                    Some(c) if c == '"' || c == '\\' => {
+                        self.advance();
+                        text.push(c);
+                    }
+                    _ => text.push('\\'),
+                },
+                Some(c) => text.push(c),
+                None => return Err(self.error(line, column, "unclosed \"", true)),
+            }
+        }
+        self.push_part(Part::Quoted(text), line, column);
+        Ok(())
+    }
+}
+
+/// Replace $name, ${name}, $1, $? etc. with their values from the context.
+/// References to variables that are not set are left as they are.
+pub fn expand_variables(text: &str, context: &Context) -> String {
+    expand_variables_with(text, context, str::to_string)
+}
+
+/// Like expand_variables, with every value, or reference left as it is,
+/// passed through f on its way into the text
+fn expand_variables_with<F: Fn(&str) -> String>(text: &str, context: &Context, f: F) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut expanded = String::with_capacity(text.len());
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        if chars[idx] != '$' {
+            expanded.push(chars[idx]);
+            idx += 1;
+            continue;
+        }
+
+        let start = idx;
+        let (name, end) = match chars.get(idx + 1) {
+            Some('{') => match chars[idx + 2..].iter().position(|c| *c == '}') {
+                Some(len) => (
+                    chars[idx + 2..idx + 2 + len].iter().collect(),
+                    idx + 3 + len,
+                ),
+                None => (String::new(), idx + 1),
+            },
+            Some(c) if matches!(c, '?' | '#' | '@') => (c.to_string(), idx + 2),
+            Some(c) if c.is_ascii_digit() => {
+                let len = chars[idx + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .count();
+                (
+                    chars[idx + 1..idx + 1 + len].iter().collect(),
+                    idx + 1 + len,
+                )
+            }
+            _ => {
+                let len = chars[idx + 1..]
+                    .iter()
+                    .take_while(|c| c.is_alphanumeric() || **c == '_')
+                    .count();
+                (
+                    chars[idx + 1..idx + 1 + len].iter().collect(),
+                    idx + 1 + len,
+                )
+            }
+        };
+
+        let key = if name == "?" {
+            STATUS_VARIABLE
+        } else {
+            name.as_str()
+        };
+        match context.get(key) {
+            Some(value) if !name.is_empty() => expanded.push_str(&f(value)),
+            _ => {
+                let reference: String = chars[start..end.max(start + 1)].iter().collect();
+                expanded.push_str(&f(&reference));
+            }
+        }
+        idx = end.max(start + 1);
+    }
+
+    expanded
+}
+
+/// the positional arguments $1, $2, ... currently set in the context
+pub fn positional_args(context: &Context) -> Vec<String> {
+    let count = context
+        .get(ARG_COUNT_VARIABLE)
+        .and_then(|c| c.parse::<usize>().ok())
+        .unwrap_or(0);
+    (1..=count)
+        .map(|n| context.get(&n.to_string()).cloned().unwrap_or_default())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> Vec<String> {
+        let mut context = Context::new();
+        context.insert("x".into(), "1 2".into());
+        tokenize(text)
+            .unwrap()
+            .iter()
+            .map(|t| match &t.kind {
+                TokenKind::Word(w) => w.expand(&context),
+                TokenKind::Separator => ";".into(),
+                TokenKind::OpenBrace => "{".into(),
+                TokenKind::CloseBrace => "}".into(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(
+            vec!["a", "b c", "d e", "f\"g"],
+            words("a 'b c' \"d e\" f\\\"g")
+        );
+        assert_eq!(
+            vec!["1 2", "$x", "$x", "${y}"],
+            words("$x '$x' \"\\$x\" ${y}")
+        );
+        assert_eq!(vec![""], words("''"));
+        assert_eq!(vec!["ab"], words("a\\\nb"));
+    }
+
+    #[test]
+    fn punctuation() {
+        assert_eq!(
+            vec!["if", "a", "{", "b", ";", "c", "}", ";"],
+            words("if a {b; c} # comment\n")
+        );
+        let tokens = tokenize("a\n  b").unwrap();
+        assert_eq!((2, 3), (tokens[2].line, tokens[2].column));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Some(6), tokenize("echo 'abc").err().map(|e| e.column));
+        assert!(tokenize("echo \"abc").is_err());
+
+        // these continue on the next line
+        for text in [
+            "echo 'abc\n",
+            "echo \"abc\n",
+            "echo \\\n",
+            "echo ${a\n",
+            "echo \\",
+        ] {
+            assert!(tokenize(text).unwrap_err().incomplete);
+        }
+        let error = tokenize("echo a\necho 'b\nc").unwrap_err();
+        assert_eq!((2, 6), (error.line, error.column));
+    }
+}
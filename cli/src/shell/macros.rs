@@ -0,0 +1,402 @@
+//! Recording command lines into named macros and replaying them.
+//!
+//! `record start <name>` begins a recording, every complete command line
+//! entered afterwards in the same session is saved together with the status
+//! it returned, and `record stop` ends the recording. `replay <name> [VAR=value...]` runs the
+//! lines again with the given context variables set for the duration of the
+//! replay. When the shell has a macro file, macros are loaded from and saved
+//! to it, one `[name]` header per macro followed by `<status> <line>` entries.
+use super::{session, Context, Shell};
+use crate::command::flag::FlagSpecSet;
+use crate::command::output::{Output, Table};
+use crate::command::{self, Command, ReturnCode};
+use crate::shell_println;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+thread_local! {
+    /// names of the macros being replayed, so a macro cannot replay itself
+    static REPLAYING: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Debug)]
+pub struct MacroError(pub String, pub String);
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error: macro {}: {}", self.0, self.1)
+    }
+}
+
+impl Error for MacroError {}
+
+/// A command line and the status it returned when it was recorded
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroEntry {
+    pub line: String,
+    pub status: i32,
+}
+
+/// Named list of recorded command lines
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Macro {
+    name: String,
+    entries: Vec<MacroEntry>,
+}
+
+impl Macro {
+    pub fn new(name: &str) -> Macro {
+        Macro {
+            name: name.into(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn entries(&self) -> &[MacroEntry] {
+        &self.entries
+    }
+
+    pub fn push(&mut self, line: &str, status: i32) {
+        self.entries.push(MacroEntry {
+            line: line.into(),
+            status,
+        });
+    }
+}
+
+/// Parse the contents of a macro file
+pub fn parse_macros(text: &str) -> Result<Vec<Macro>, Box<dyn Error>> {
+    let mut macros: Vec<Macro> = Vec::new();
+
+    for (idx, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            macros.push(Macro::new(name));
+            continue;
+        }
+
+        let invalid = || {
+            MacroError(
+                format!("file line {}", idx + 1),
+                "expected '<status> <line>'".into(),
+            )
+        };
+        let current = macros.last_mut().ok_or_else(invalid)?;
+        let (status, line) = line.split_once(' ').ok_or_else(invalid)?;
+        current.push(&unescape(line), status.parse().map_err(|_| invalid())?);
+    }
+
+    Ok(macros)
+}
+
+/// Write macros in the format read by parse_macros
+pub fn format_macros<'a, I: IntoIterator<Item = &'a Macro>>(macros: I) -> String {
+    let mut text = String::new();
+    for m in macros {
+        text.push_str(&format!("[{}]\n", m.name));
+        for entry in m.entries.iter() {
+            text.push_str(&format!("{} {}\n", entry.status, escape(&entry.line)));
+        }
+    }
+    text
+}
+
+/// keep multi-line command lines on one line of the file
+fn escape(line: &str) -> String {
+    line.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                text.push('\n');
+            }
+            ('\\', Some('\\')) => {
+                chars.next();
+                text.push('\\');
+            }
+            (c, _) => text.push(c),
+        }
+    }
+    text
+}
+
+impl Shell {
+    /// Start recording the command lines of the current session into a macro
+    /// with the given name
+    pub fn start_recording(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        if name.is_empty() || name.contains(['[', ']', '\n']) {
+            return Err(Box::new(MacroError(
+                name.into(),
+                "invalid macro name".into(),
+            )));
+        }
+
+        let started = session::with_recording(|recording| match recording {
+            Some(current) => Err(MacroError(current.name.clone(), "already recording".into())),
+            None => {
+                *recording = Some(Macro::new(name));
+                Ok(())
+            }
+        });
+        match started {
+            Some(result) => Ok(result?),
+            None => Err(Box::new(MacroError(
+                name.into(),
+                "only a shell session can be recorded".into(),
+            ))),
+        }
+    }
+
+    /// Stop the recording of the current session, store the macro and save
+    /// the macro file
+    pub fn stop_recording(&self) -> Result<Macro, Box<dyn Error>> {
+        let recorded = match session::with_recording(Option::take).flatten() {
+            Some(recorded) => recorded,
+            None => return Err(Box::new(MacroError(String::new(), "not recording".into()))),
+        };
+
+        self.macros
+            .write()
+            .unwrap()
+            .insert(recorded.name.clone(), recorded.clone());
+        self.save_macros()?;
+        Ok(recorded)
+    }
+
+    /// whether the current session is recording
+    pub fn is_recording(&self) -> bool {
+        session::with_recording(|recording| recording.is_some()).unwrap_or(false)
+    }
+
+    /// Add a command line to the macro the current session records, if any
+    pub fn record_line(&self, line: &str, code: &ReturnCode) {
+        let line = line.trim_end_matches('\n');
+        if line.trim().is_empty() {
+            return;
+        }
+        session::with_recording(|recording| {
+            if let Some(recording) = recording {
+                recording.push(line, code.status());
+            }
+        });
+    }
+
+    /// a copy of a stored macro
+    pub fn find_macro(&self, name: &str) -> Option<Macro> {
+        self.macros.read().unwrap().get(name).cloned()
+    }
+
+    /// all stored macros, sorted by name
+    pub fn macros(&self) -> Vec<Macro> {
+        self.macros.read().unwrap().values().cloned().collect()
+    }
+
+    /// Read the macros stored in the macro file, replacing those in memory
+    pub fn load_macros(&self) -> Result<(), Box<dyn Error>> {
+        let path = match self.macro_file {
+            Some(ref path) if Path::new(path).exists() => path,
+            _ => return Ok(()),
+        };
+
+        let loaded: BTreeMap<String, Macro> = parse_macros(&fs::read_to_string(path)?)?
+            .into_iter()
+            .map(|m| (m.name.clone(), m))
+            .collect();
+        *self.macros.write().unwrap() = loaded;
+        Ok(())
+    }
+
+    /// Write every stored macro to the macro file
+    pub fn save_macros(&self) -> Result<(), Box<dyn Error>> {
+        match self.macro_file {
+            Some(ref path) => Ok(fs::write(
+                path,
+                format_macros(self.macros.read().unwrap().values()),
+            )?),
+            None => Ok(()),
+        }
+    }
+
+    /// Run the lines of a macro with the overrides set as context variables.
+    /// The variables get their previous values back afterwards. A line that
+    /// returns a different status than it did when recorded is reported.
+    pub fn replay(
+        &self,
+        name: &str,
+        overrides: &[(String, String)],
+        context: &mut Context,
+    ) -> Result<ReturnCode, Box<dyn Error>> {
+        let recorded = self
+            .find_macro(name)
+            .ok_or_else(|| MacroError(name.into(), "not found".into()))?;
+        if REPLAYING.with(|r| r.borrow().iter().any(|n| n == name)) {
+            return Err(Box::new(MacroError(name.into(), "replays itself".into())));
+        }
+
+        let saved: Vec<(String, Option<String>)> = overrides
+            .iter()
+            .map(|(key, value)| (key.clone(), context.insert(key.clone(), value.clone())))
+            .collect();
+
+        REPLAYING.with(|r| r.borrow_mut().push(name.into()));
+        let mut result = Ok(ReturnCode::Ok);
+        for (idx, entry) in recorded.entries.iter().enumerate() {
+            result = self.execute(&entry.line, context);
+            let status = match result {
+                Ok(ref code) if *code == ReturnCode::Abort => break,
+                Ok(ref code) => code.status(),
+                Err(_) => break,
+            };
+            if status != entry.status {
+                shell_println!(
+                    "replay {}: line {} returned {}, recorded {}",
+                    name,
+                    idx + 1,
+                    status,
+                    entry.status
+                );
+            }
+        }
+        REPLAYING.with(|r| r.borrow_mut().pop());
+
+        for (key, value) in saved.into_iter().rev() {
+            match value {
+                Some(value) => context.insert(key, value),
+                None => context.remove(&key),
+            };
+        }
+        result
+    }
+}
+
+/// The `record` builtin, `record start <name>` or `record stop`
+pub fn record_config() -> command::Config {
+    command::Config::new(
+        "record",
+        FlagSpecSet::new(),
+        "Record command lines into a macro: record start <name> / record stop",
+        |command: &Command,
+         shell: &Shell,
+         _context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            let operands: Vec<&str> = command.operands().iter().map(|o| o.value()).collect();
+            match operands[..] {
+                ["start", name] => shell.start_recording(name)?,
+                ["stop"] => {
+                    let recorded = shell.stop_recording()?;
+                    shell_println!(
+                        "recorded {} lines into {}",
+                        recorded.entries.len(),
+                        recorded.name
+                    );
+                }
+                _ => {
+                    return Err(Box::new(MacroError(
+                        operands.join(" "),
+                        "usage: record start <name> | record stop".into(),
+                    )))
+                }
+            }
+            Ok(ReturnCode::Ok)
+        },
+    )
+}
+
+/// The `replay` builtin, `replay <name> [VAR=value...]`. Without operands it
+/// lists the stored macros.
+pub fn replay_config() -> command::Config {
+    command::Config::new(
+        "replay",
+        FlagSpecSet::new(),
+        "Run a recorded macro again: replay <name> [VAR=value...]",
+        |command: &Command,
+         shell: &Shell,
+         context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            let operands = command.operands();
+            let name = match operands.first() {
+                Some(name) => name.value(),
+                None => {
+                    let mut table = Table::new(&["name", "lines"]);
+                    for m in shell.macros() {
+                        table.push_row(vec![m.name.into(), (m.entries.len() as i64).into()]);
+                    }
+                    return Ok(ReturnCode::Data(Output::Table(table)));
+                }
+            };
+
+            let mut overrides = Vec::new();
+            for operand in operands[1..].iter() {
+                match operand.value().split_once('=') {
+                    Some((key, value)) if !key.is_empty() => {
+                        overrides.push((key.into(), value.into()))
+                    }
+                    _ => {
+                        return Err(Box::new(MacroError(
+                            name.into(),
+                            format!("expected VAR=value, got {}", operand.value()),
+                        )))
+                    }
+                }
+            }
+
+            shell.replay(name, &overrides, context)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_format() {
+        let mut first = Macro::new("build");
+        first.push("calc 1 + $x", 0);
+        first.push("if a {\n  b \\\n c\n}", 1);
+        let second = Macro::new("empty");
+
+        let text = format_macros([&first, &second]);
+        assert_eq!(vec![first, second], parse_macros(&text).unwrap());
+        assert!(parse_macros("0 orphan line").is_err());
+        assert!(parse_macros("[m]\nx line").is_err());
+    }
+
+    #[test]
+    fn recordings_belong_to_a_session() {
+        let shell = Shell::new(crate::shell::CommandSet::new(), "");
+        let mut first = session::Session::new(std::io::empty(), std::io::sink());
+        let mut second = session::Session::new(std::io::empty(), std::io::sink());
+
+        assert!(shell.start_recording("m").is_err());
+        {
+            let _guard = first.enter();
+            shell.start_recording("m").unwrap();
+            assert!(shell.is_recording());
+        }
+        {
+            let _guard = second.enter();
+            assert!(!shell.is_recording());
+            shell.record_line("calc 1", &ReturnCode::Ok);
+            assert!(shell.stop_recording().is_err());
+        }
+        let _guard = first.enter();
+        shell.record_line("calc 2", &ReturnCode::Ok);
+        assert_eq!(1, shell.stop_recording().unwrap().entries().len());
+    }
+}
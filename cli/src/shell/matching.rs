@@ -0,0 +1,290 @@
+//! Settings for how typed names are matched against registered commands and
+//! flags: ignoring case and normalizing Unicode.
+//!
+//! Normalization covers the compatibility forms people actually type by
+//! accident: fullwidth ASCII, non-breaking and ideographic spaces, the fi/fl
+//! ligatures, and a Latin letter followed by a combining accent, which is
+//! composed into the single accented letter. It is a practical subset of
+//! Unicode NFKC, not a full implementation.
+use super::CommandSet;
+use crate::command;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// combining mark, the letters it composes with and the composed letters
+const COMPOSITIONS: [(char, &str, &str); 7] = [
+    ('\u{300}', "AEIOUaeiou", "ÀÈÌÒÙàèìòù"),
+    ('\u{301}', "AEIOUYaeiouy", "ÁÉÍÓÚÝáéíóúý"),
+    ('\u{302}', "AEIOUaeiou", "ÂÊÎÔÛâêîôû"),
+    ('\u{303}', "ANOano", "ÃÑÕãñõ"),
+    ('\u{308}', "AEIOUaeiouy", "ÄËÏÖÜäëïöüÿ"),
+    ('\u{30A}', "Aa", "Åå"),
+    ('\u{327}', "Cc", "Çç"),
+];
+
+/// Two names that can no longer be told apart under the match options
+#[derive(Debug)]
+pub struct NameCollisionError(pub String, pub String, pub String);
+
+impl fmt::Display for NameCollisionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Error: {} '{}' and '{}' collide under the shell's name matching",
+            self.0, self.1, self.2
+        )
+    }
+}
+
+impl Error for NameCollisionError {}
+
+/// How names typed by the user are compared with registered names. The
+/// default is exact matching.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MatchOptions {
+    case_insensitive_commands: bool,
+    case_insensitive_flags: bool,
+    normalize_unicode: bool,
+}
+
+impl MatchOptions {
+    pub fn new() -> MatchOptions {
+        MatchOptions::default()
+    }
+
+    /// match command names regardless of case, e.g. `LIST` runs `list`
+    pub fn case_insensitive_commands(mut self, enabled: bool) -> MatchOptions {
+        self.case_insensitive_commands = enabled;
+        self
+    }
+
+    /// match long flag names regardless of case, e.g. `--Verbose`
+    pub fn case_insensitive_flags(mut self, enabled: bool) -> MatchOptions {
+        self.case_insensitive_flags = enabled;
+        self
+    }
+
+    /// normalize typed command and flag names before matching, e.g. a
+    /// fullwidth `ｌｉｓｔ` runs `list`; operands are left as typed
+    pub fn normalize_unicode(mut self, enabled: bool) -> MatchOptions {
+        self.normalize_unicode = enabled;
+        self
+    }
+
+    pub fn normalizes_unicode(&self) -> bool {
+        self.normalize_unicode
+    }
+
+    /// whether names are compared exactly as typed
+    pub fn is_exact(&self) -> bool {
+        *self == MatchOptions::default()
+    }
+
+    /// the form of a command name used for comparison
+    pub fn command_key(&self, name: &str) -> String {
+        self.key(name, self.case_insensitive_commands)
+    }
+
+    /// the form of a long flag name used for comparison
+    pub fn flag_key(&self, name: &str) -> String {
+        self.key(name, self.case_insensitive_flags)
+    }
+
+    fn key(&self, name: &str, fold_case: bool) -> String {
+        let name = match self.normalize_unicode {
+            true => normalize(name),
+            false => name.to_string(),
+        };
+        match fold_case {
+            true => name.to_lowercase(),
+            false => name,
+        }
+    }
+
+    /// Check that every command, and every long flag within a command, still
+    /// has a name of its own under these options.
+    pub fn check_collisions(&self, commands: &CommandSet) -> Result<(), NameCollisionError> {
+        let mut names: Vec<&str> = commands.keys().map(String::as_str).collect();
+        names.sort();
+        find_collision(&names, |n| self.command_key(n)).map_or(Ok(()), |(a, b)| {
+            Err(NameCollisionError("commands".into(), a, b))
+        })?;
+
+        for name in names {
+            self.check_flag_collisions(&commands[name])?;
+        }
+        Ok(())
+    }
+
+    /// Check that a command about to be added still has a name of its own
+    /// among the others, and that its long flags do. A command with the very
+    /// same name is replaced rather than colliding.
+    pub fn check_new_command(
+        &self,
+        commands: &CommandSet,
+        config: &command::Config,
+    ) -> Result<(), NameCollisionError> {
+        let key = self.command_key(config.name());
+        let mut names: Vec<&str> = commands
+            .keys()
+            .map(String::as_str)
+            .filter(|n| *n != config.name() && self.command_key(n) == key)
+            .collect();
+        names.sort();
+        if let Some(name) = names.first() {
+            return Err(NameCollisionError(
+                "commands".into(),
+                name.to_string(),
+                config.name().into(),
+            ));
+        }
+        self.check_flag_collisions(config)
+    }
+
+    fn check_flag_collisions(&self, config: &command::Config) -> Result<(), NameCollisionError> {
+        let mut flags: Vec<&str> = config.get_flags().iter().map(|f| f.name()).collect();
+        flags.sort();
+        match find_collision(&flags, |n| self.flag_key(n)) {
+            Some((a, b)) => Err(NameCollisionError(
+                format!("flags of {}", config.name()),
+                a,
+                b,
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// the first two names with the same key
+fn find_collision<F: Fn(&str) -> String>(names: &[&str], key: F) -> Option<(String, String)> {
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    for name in names {
+        if let Some(previous) = seen.insert(key(name), name) {
+            return Some((previous.into(), name.to_string()));
+        }
+    }
+    None
+}
+
+/// Replace compatibility characters and compose accented Latin letters
+pub fn normalize(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            // fullwidth forms of the printable ASCII characters
+            '\u{FF01}'..='\u{FF5E}' => {
+                normalized.push(char::from_u32(c as u32 - 0xFF01 + 0x21).unwrap())
+            }
+            '\u{A0}' | '\u{2007}' | '\u{202F}' | '\u{3000}' => normalized.push(' '),
+            '\u{FB00}' => normalized.push_str("ff"),
+            '\u{FB01}' => normalized.push_str("fi"),
+            '\u{FB02}' => normalized.push_str("fl"),
+            c => match compose(normalized.chars().last(), c) {
+                Some(composed) => {
+                    normalized.pop();
+                    normalized.push(composed);
+                }
+                None => normalized.push(c),
+            },
+        }
+    }
+    normalized
+}
+
+/// the letter composed of base and a combining mark, if there is one
+fn compose(base: Option<char>, mark: char) -> Option<char> {
+    let base = base?;
+    let (_, bases, composed) = COMPOSITIONS.iter().find(|(m, _, _)| *m == mark)?;
+    let idx = bases.chars().position(|b| b == base)?;
+    composed.chars().nth(idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::flag::{ArgSpec, FlagSpec, FlagSpecSet};
+    use crate::command::{self, Command, ReturnCode};
+    use crate::shell::{Context, Shell};
+
+    fn noop(_: &Command, _: &Shell, _: &mut Context) -> Result<ReturnCode, Box<dyn Error>> {
+        Ok(ReturnCode::Ok)
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!("list -a", normalize("ｌｉｓｔ\u{3000}－ａ"));
+        assert_eq!("café", normalize("cafe\u{301}"));
+        assert_eq!("file", normalize("\u{FB01}le"));
+        assert_eq!("x\u{301}", normalize("x\u{301}"));
+    }
+
+    #[test]
+    fn collisions() {
+        let mut flags = FlagSpecSet::new();
+        flags.insert(FlagSpec::new("all", 'a', ArgSpec::default(), ""));
+        flags.insert(FlagSpec::new("All", 'A', ArgSpec::default(), ""));
+
+        let mut commands = CommandSet::new();
+        commands.insert("list".into(), command::Config::new("list", flags, "", noop));
+        commands.insert(
+            "List".into(),
+            command::Config::new("List", FlagSpecSet::new(), "", noop),
+        );
+
+        let options = MatchOptions::new();
+        assert!(options.check_collisions(&commands).is_ok());
+        let error = options
+            .case_insensitive_commands(true)
+            .check_collisions(&commands)
+            .unwrap_err();
+        assert_eq!("commands", error.0);
+
+        commands.remove("List");
+        let error = options
+            .case_insensitive_flags(true)
+            .check_collisions(&commands)
+            .unwrap_err();
+        assert_eq!(("All", "all"), (error.1.as_str(), error.2.as_str()));
+    }
+
+    #[test]
+    fn registering_and_defining() {
+        let mut commands = CommandSet::new();
+        commands.insert(
+            "list".into(),
+            command::Config::new("list", FlagSpecSet::new(), "", noop),
+        );
+        let shell = Shell::new(commands, "")
+            .with_matching(MatchOptions::new().case_insensitive_commands(true))
+            .unwrap();
+
+        let config = command::Config::new("List", FlagSpecSet::new(), "", noop);
+        assert!(shell.register_command(config).is_err());
+        let config = command::Config::new("list", FlagSpecSet::new(), "", noop);
+        assert!(shell.register_command(config).unwrap().is_some());
+
+        let mut context = Context::new();
+        shell.execute("fn greet { a }", &mut context).unwrap();
+        assert!(shell.execute("fn GREET { b }", &mut context).is_err());
+        assert!(shell.execute("fn greet { b }", &mut context).is_ok());
+        assert!(shell.find_command_config("GREET").is_some());
+        assert!(shell.function_body("GREET").is_none());
+    }
+
+    #[test]
+    fn only_names_are_normalized() {
+        let mut commands = CommandSet::new();
+        commands.insert("set".into(), crate::builtins::set());
+        let shell = Shell::new(commands, "")
+            .with_matching(MatchOptions::new().normalize_unicode(true))
+            .unwrap();
+
+        let mut context = Context::new();
+        shell
+            .run_args(&["ｓｅｔ", "name", "ｃａｆｅ\u{301}"], &mut context)
+            .unwrap();
+        assert_eq!("ｃａｆｅ\u{301}", context["name"]);
+    }
+}
@@ -0,0 +1,453 @@
+//! Prompt templates. A template is plain text with `%` sequences that are
+//! replaced every time the prompt is shown:
+//!
+//! * `%?` exit status of the last command
+//! * `%T` current local time as HH:MM:SS (UTC where the local time zone
+//!   can't be looked up)
+//! * `%j` number of background jobs, read from the "jobs" context variable
+//! * `%v(name)` value of a context variable
+//! * `%x(command args...)` first line of output of an external command,
+//!   e.g. `%x(git branch --show-current)`, or nothing if it takes longer
+//!   than half a second
+//! * `%F(color)` / `%f` start / end a color, one of black, red, green,
+//!   yellow, blue, magenta, cyan and white, or `status` for green after a
+//!   successful command and red otherwise
+//! * `%B` / `%b` start / end bold text
+//! * `%%` a literal '%'
+//!
+//! Colors are only written when the session writes to a terminal. A "prompt"
+//! value without any `%` sequence is a plain prompt from before templates,
+//! and is still shown with a '>' after it.
+use super::lexer::STATUS_VARIABLE;
+use super::{session, Context, Shell, CONTEXT_CONTINUATION_PROMPT, CONTEXT_PROMPT_STRING};
+use crate::command::flag::{self, ArgSpec, FlagQuery, FlagSpec, FlagSpecSet};
+use crate::command::{self, Command, ReturnCode};
+use crate::shell_println;
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// prompt used when the context does not set one
+pub const DEFAULT_PROMPT: &str = "#>";
+
+/// prompt used while more lines of a command are expected
+pub const DEFAULT_CONTINUATION_PROMPT: &str = "...";
+
+/// context variable holding the number of background jobs
+pub const CONTEXT_JOB_COUNT: &str = "jobs";
+
+/// longest a `%x` command may run before the prompt is shown without it
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(500);
+
+const ANSI_RESET: &str = "\x1b[0m";
+const ANSI_BOLD: &str = "\x1b[1m";
+
+#[derive(Debug)]
+pub struct PromptError(pub usize, pub String);
+
+impl fmt::Display for PromptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Error: invalid prompt template at offset {}: {}",
+            self.0, self.1
+        )
+    }
+}
+
+impl Error for PromptError {}
+
+/// one piece of a parsed template
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Status,
+    Time,
+    Jobs,
+    Variable(String),
+    CommandOutput(String),
+    Color(String),
+    EndColor,
+    Bold,
+    EndBold,
+}
+
+fn parse(template: &str) -> Result<Vec<Segment>, PromptError> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+
+        let code = match chars.next() {
+            Some((_, code)) => code,
+            None => return Err(PromptError(offset, "'%' at the end of the template".into())),
+        };
+        let mut argument = || -> Result<String, PromptError> {
+            if !matches!(chars.next(), Some((_, '('))) {
+                return Err(PromptError(
+                    offset,
+                    format!("%{} needs an argument in parentheses", code),
+                ));
+            }
+            let mut arg = String::new();
+            for (_, c) in chars.by_ref() {
+                if c == ')' {
+                    return Ok(arg);
+                }
+                arg.push(c);
+            }
+            Err(PromptError(
+                offset,
+                format!("unclosed parenthesis after %{}", code),
+            ))
+        };
+
+        let segment = match code {
+            '%' => {
+                text.push('%');
+                continue;
+            }
+            '?' => Segment::Status,
+            'T' => Segment::Time,
+            'j' => Segment::Jobs,
+            'v' => Segment::Variable(argument()?),
+            'x' => Segment::CommandOutput(argument()?),
+            'F' => {
+                let color = argument()?;
+                if color != "status" && color_code(&color).is_none() {
+                    return Err(PromptError(offset, format!("unknown color '{}'", color)));
+                }
+                Segment::Color(color)
+            }
+            'f' => Segment::EndColor,
+            'B' => Segment::Bold,
+            'b' => Segment::EndBold,
+            _ => return Err(PromptError(offset, format!("unknown sequence %{}", code))),
+        };
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(std::mem::take(&mut text)));
+        }
+        segments.push(segment);
+    }
+
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    Ok(segments)
+}
+
+/// check a template without rendering it
+pub fn validate(template: &str) -> Result<(), PromptError> {
+    parse(template).map(|_| ())
+}
+
+/// Render a template. With color set to false all color and bold sequences
+/// are dropped.
+pub fn render(template: &str, context: &Context, color: bool) -> Result<String, PromptError> {
+    let status = context
+        .get(STATUS_VARIABLE)
+        .map(String::as_str)
+        .unwrap_or("0");
+    let mut prompt = String::new();
+
+    for segment in parse(template)? {
+        match segment {
+            Segment::Text(text) => prompt.push_str(&text),
+            Segment::Status => prompt.push_str(status),
+            Segment::Time => prompt.push_str(&time_of_day()),
+            Segment::Jobs => prompt.push_str(
+                context
+                    .get(CONTEXT_JOB_COUNT)
+                    .map(String::as_str)
+                    .unwrap_or("0"),
+            ),
+            Segment::Variable(name) => {
+                prompt.push_str(context.get(&name).map(String::as_str).unwrap_or(""))
+            }
+            Segment::CommandOutput(command_line) => prompt.push_str(&command_output(&command_line)),
+            Segment::Color(_) | Segment::EndColor | Segment::Bold | Segment::EndBold if !color => {}
+            Segment::Color(name) => {
+                let name = match name.as_str() {
+                    "status" if status == "0" => "green",
+                    "status" => "red",
+                    name => name,
+                };
+                prompt.push_str(&format!("\x1b[{}m", color_code(name).unwrap_or(39)));
+            }
+            Segment::EndColor => prompt.push_str("\x1b[39m"),
+            Segment::Bold => prompt.push_str(ANSI_BOLD),
+            Segment::EndBold => prompt.push_str("\x1b[22m"),
+        }
+    }
+
+    if color {
+        prompt.push_str(ANSI_RESET);
+    }
+    Ok(prompt)
+}
+
+/// render the template from the context, or the default one
+pub fn make_prompt(context: &Context) -> String {
+    match context.get(CONTEXT_PROMPT_STRING) {
+        Some(value) if !value.contains('%') => format!("{}>", value),
+        _ => make_from(context, CONTEXT_PROMPT_STRING, DEFAULT_PROMPT),
+    }
+}
+
+/// render the continuation template from the context, or the default one
+pub fn make_continuation_prompt(context: &Context) -> String {
+    make_from(
+        context,
+        CONTEXT_CONTINUATION_PROMPT,
+        DEFAULT_CONTINUATION_PROMPT,
+    )
+}
+
+fn make_from(context: &Context, key: &str, default: &str) -> String {
+    let template = context.get(key).map(String::as_str).unwrap_or(default);
+
+    // a broken template is shown as is rather than leaving the user without a prompt
+    render(template, context, session::is_terminal()).unwrap_or_else(|_| template.into())
+}
+
+fn color_code(name: &str) -> Option<u8> {
+    let colors = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    colors
+        .iter()
+        .position(|c| *c == name)
+        .map(|idx| 30 + idx as u8)
+}
+
+fn time_of_day() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (hours, minutes, seconds) =
+        local_time(now).unwrap_or((now / 3600 % 24, now / 60 % 60, now % 60));
+    format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+}
+
+/// hours, minutes and seconds of a unix time in the local time zone
+#[cfg(unix)]
+fn local_time(unix_time: u64) -> Option<(u64, u64, u64)> {
+    use std::os::raw::{c_int, c_long};
+
+    /// the C library's struct tm; the fields after tm_isdst differ between
+    /// platforms and only need room
+    #[repr(C)]
+    struct Tm {
+        sec: c_int,
+        min: c_int,
+        hour: c_int,
+        rest: [c_int; 6],
+        tail: [usize; 4],
+    }
+
+    extern "C" {
+        fn localtime_r(time: *const c_long, result: *mut Tm) -> *mut Tm;
+    }
+
+    let time = c_long::try_from(unix_time).ok()?;
+    let mut tm = Tm {
+        sec: 0,
+        min: 0,
+        hour: 0,
+        rest: [0; 6],
+        tail: [0; 4],
+    };
+    // SAFETY: both pointers are valid for the call, and Tm is at least as
+    // large as the struct tm it fills in
+    let result = unsafe { localtime_r(&time, &mut tm) };
+    if result.is_null() {
+        return None;
+    }
+    Some((tm.hour as u64, tm.min as u64, tm.sec as u64))
+}
+
+#[cfg(not(unix))]
+fn local_time(_unix_time: u64) -> Option<(u64, u64, u64)> {
+    None
+}
+
+/// first line of stdout of a command, or nothing if it could not be run,
+/// failed or did not finish within COMMAND_TIMEOUT
+fn command_output(command_line: &str) -> String {
+    let mut words = command_line.split_whitespace();
+    let program = match words.next() {
+        Some(program) => program,
+        None => return String::new(),
+    };
+
+    let mut child = match process::Command::new(program)
+        .args(words)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => return String::new(),
+    };
+
+    // read on another thread, so that a command that never closes its
+    // output can't hold up the prompt
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stdout.read_to_end(&mut output);
+        let _ = sender.send(output);
+    });
+
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    let output = receiver.recv_timeout(COMMAND_TIMEOUT).ok();
+    let status = loop {
+        match child.try_wait() {
+            Ok(None) if output.is_some() && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(5))
+            }
+            Ok(status) => break status,
+            Err(_) => break None,
+        }
+    };
+
+    match (status, output) {
+        (Some(status), Some(output)) if status.success() => String::from_utf8_lossy(&output)
+            .lines()
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_string(),
+        (None, _) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            String::new()
+        }
+        _ => String::new(),
+    }
+}
+
+/// The `prompt` builtin. Without operands it shows the current template,
+/// otherwise the operands are joined into the new template.
+pub fn config() -> command::Config {
+    let mut flags = FlagSpecSet::new();
+    flags.insert(FlagSpec::new(
+        "reset",
+        'r',
+        ArgSpec::default(),
+        "Go back to the default prompt",
+    ));
+    flags.insert(FlagSpec::new(
+        "continuation",
+        'c',
+        ArgSpec::default(),
+        "Work on the prompt shown while a command continues on the next line",
+    ));
+
+    command::Config::new(
+        "prompt",
+        flags,
+        "Show or change the prompt template, e.g. prompt '%F(status)%?%f %v(PWD)>'",
+        |command: &Command,
+         _shell: &Shell,
+         context: &mut Context|
+         -> Result<ReturnCode, Box<dyn Error>> {
+            let (key, default) =
+                match flag::query_flag(&FlagQuery::Name("continuation".into()), command.flags()) {
+                    Some(_) => (CONTEXT_CONTINUATION_PROMPT, DEFAULT_CONTINUATION_PROMPT),
+                    None => (CONTEXT_PROMPT_STRING, DEFAULT_PROMPT),
+                };
+
+            if flag::query_flag(&FlagQuery::Name("reset".into()), command.flags()).is_some() {
+                context.remove(key);
+                return Ok(ReturnCode::Ok);
+            }
+
+            if command.operands().is_empty() {
+                shell_println!(
+                    "{}",
+                    context.get(key).map(String::as_str).unwrap_or(default)
+                );
+                return Ok(ReturnCode::Ok);
+            }
+
+            let template = command
+                .operands()
+                .iter()
+                .map(|o| o.value())
+                .collect::<Vec<&str>>()
+                .join(" ");
+            validate(&template)?;
+            context.insert(key.into(), template);
+            Ok(ReturnCode::Ok)
+        },
+    )
+    .no_glob()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments() {
+        let mut context = Context::new();
+        context.insert(STATUS_VARIABLE.into(), "2".into());
+        context.insert("name".into(), "dev".into());
+
+        assert_eq!("#>", render(DEFAULT_PROMPT, &context, false).unwrap());
+        assert_eq!(
+            "[2] dev 0% >",
+            render("[%?] %v(name) %j%% >", &context, false).unwrap()
+        );
+        assert_eq!("ok>", render("%B%F(red)ok%f%b>", &context, false).unwrap());
+        assert_eq!(
+            "\x1b[31m2\x1b[39m>\x1b[0m",
+            render("%F(status)%?%f>", &context, true).unwrap()
+        );
+        assert_eq!(8, render("%T", &context, false).unwrap().len());
+    }
+
+    #[test]
+    fn plain_prompt() {
+        let mut context = Context::new();
+        assert_eq!("#>", make_prompt(&context));
+        context.insert(CONTEXT_PROMPT_STRING.into(), "dev".into());
+        assert_eq!("dev>", make_prompt(&context));
+        context.insert(CONTEXT_PROMPT_STRING.into(), "%?$".into());
+        assert_eq!("0$", make_prompt(&context));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_timeout() {
+        let context = Context::new();
+        assert_eq!("hi", render("%x(echo hi)", &context, false).unwrap());
+
+        let start = Instant::now();
+        assert_eq!(">", render("%x(sleep 5)>", &context, false).unwrap());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(validate("%").is_err());
+        assert!(validate("%q").is_err());
+        assert!(validate("%v(name").is_err());
+        assert!(validate("%F(pink)").is_err());
+        assert!(validate("%x").is_err());
+    }
+}
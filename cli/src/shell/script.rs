@@ -0,0 +1,674 @@
+//! Statements and control flow on top of plain command lines.
+//!
+//! ```text
+//! if [!] <command> { ... } else if <command> { ... } else { ... }
+//! while [!] <command> { ... }
+//! for <name> in <words and globs> { ... }
+//! fn <name> { ... }
+//! ```
+//!
+//! Conditions are commands; the branch is taken when the command succeeds,
+//! i.e. its ReturnCode has status 0. Statements are separated by newlines or
+//! ';' and blocks may span several lines. A function registers a new command
+//! with the shell; inside its body the operands it was called with are
+//! available as $1, $2, ..., their count as $# and all of them as $@.
+//!
+//! Unquoted wildcards in operands and for lists are expanded to the sorted
+//! file names they match (see the glob module). A pattern that matches
+//! nothing is kept as it was typed, unless the "glob_nomatch" context
+//! variable is set to "error", which makes it an error. Commands configured
+//! with no_glob get their operands unexpanded.
+use super::lexer::{self, SyntaxError, Token, TokenKind, Word};
+use super::{glob, Context, Shell};
+use crate::command::flag::FlagSpecSet;
+use crate::command::{self, Command, ReturnCode};
+use crate::shell_println;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+/// help text shown for commands defined with `fn`
+const FUNCTION_HELP: &str = "User defined function";
+
+/// nesting limit for function calls, so that runaway recursion becomes an
+/// error instead of a stack overflow
+const MAX_CALL_DEPTH: usize = 256;
+
+/// context variable choosing what happens to a pattern that matches
+/// nothing: it stays literal (the default) or is an error
+pub const GLOB_NOMATCH_VARIABLE: &str = "glob_nomatch";
+pub const GLOB_NOMATCH_LITERAL: &str = "literal";
+pub const GLOB_NOMATCH_ERROR: &str = "error";
+
+const KEYWORDS: [&str; 6] = ["if", "else", "while", "for", "in", "fn"];
+
+thread_local! {
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+#[derive(Debug)]
+pub struct CommandExistsError(pub String);
+
+impl fmt::Display for CommandExistsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error: cannot redefine command {}", self.0)
+    }
+}
+
+impl Error for CommandExistsError {}
+
+#[derive(Debug)]
+pub struct RecursionLimitError(pub String);
+
+impl fmt::Display for RecursionLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Error: function {} nested deeper than {} calls",
+            self.0, MAX_CALL_DEPTH
+        )
+    }
+}
+
+impl Error for RecursionLimitError {}
+
+/// A command whose ReturnCode decides which way a branch or loop goes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    negated: bool,
+    command: Vec<Word>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    Command(Vec<Word>),
+    If {
+        condition: Condition,
+        then: Vec<Statement>,
+        otherwise: Vec<Statement>,
+    },
+    While {
+        condition: Condition,
+        body: Vec<Statement>,
+    },
+    For {
+        variable: String,
+        items: Vec<Word>,
+        body: Vec<Statement>,
+    },
+    Function {
+        name: String,
+        body: Arc<Vec<Statement>>,
+    },
+}
+
+/// Parse script text into statements
+pub fn parse(text: &str) -> Result<Vec<Statement>, SyntaxError> {
+    let tokens = lexer::tokenize(text)?;
+    let (line, column) = text
+        .lines()
+        .enumerate()
+        .last()
+        .map(|(idx, l)| (idx + 1, l.chars().count() + 1))
+        .unwrap_or((1, 1));
+
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: (line, column),
+    };
+    parser.statements(false)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// position just past the end of the input, for errors at the end
+    end: (usize, usize),
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_word(&self) -> Option<&Word> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Word(w),
+                ..
+            }) => Some(w),
+            _ => None,
+        }
+    }
+
+    fn error_here(&self, message: &str) -> SyntaxError {
+        match self.peek() {
+            Some(token) => SyntaxError {
+                line: token.line,
+                column: token.column,
+                message: message.into(),
+                incomplete: false,
+            },
+            None => SyntaxError {
+                line: self.end.0,
+                column: self.end.1,
+                message: message.into(),
+                incomplete: true,
+            },
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while let Some(Token {
+            kind: TokenKind::Separator,
+            ..
+        }) = self.peek()
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<(), SyntaxError> {
+        match self.peek() {
+            Some(token) if token.kind == kind => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error_here(&format!("expected {}", what))),
+        }
+    }
+
+    /// statements up to the end of input, or up to and including the
+    /// closing brace when inside a block
+    fn statements(&mut self, in_block: bool) -> Result<Vec<Statement>, SyntaxError> {
+        let mut statements = Vec::new();
+
+        loop {
+            self.skip_separators();
+            match self.peek().map(|t| &t.kind) {
+                None if in_block => return Err(self.error_here("expected '}'")),
+                None => return Ok(statements),
+                Some(TokenKind::CloseBrace) if in_block => {
+                    self.pos += 1;
+                    return Ok(statements);
+                }
+                Some(TokenKind::CloseBrace) => return Err(self.error_here("unexpected '}'")),
+                _ => statements.push(self.statement()?),
+            }
+
+            match self.peek().map(|t| &t.kind) {
+                None | Some(TokenKind::Separator) | Some(TokenKind::CloseBrace) => (),
+                _ => return Err(self.error_here("expected a new line or ';'")),
+            }
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, SyntaxError> {
+        self.expect(TokenKind::OpenBrace, "'{'")?;
+        self.statements(true)
+    }
+
+    fn statement(&mut self) -> Result<Statement, SyntaxError> {
+        let keyword = self
+            .peek_word()
+            .and_then(|w| w.as_bare())
+            .unwrap_or_default();
+        match keyword {
+            "if" => self.if_statement(),
+            "while" => {
+                self.pos += 1;
+                let condition = self.condition()?;
+                let body = self.block()?;
+                Ok(Statement::While { condition, body })
+            }
+            "for" => {
+                self.pos += 1;
+                let variable = self.name("a variable name")?;
+                match self.peek_word() {
+                    Some(w) if w.is_bare("in") => self.pos += 1,
+                    _ => return Err(self.error_here("expected 'in'")),
+                }
+                let items = self.words();
+                let body = self.block()?;
+                Ok(Statement::For {
+                    variable,
+                    items,
+                    body,
+                })
+            }
+            "fn" => {
+                self.pos += 1;
+                let name = self.name("a function name")?;
+                let body = Arc::new(self.block()?);
+                Ok(Statement::Function { name, body })
+            }
+            "else" | "in" => Err(self.error_here(&format!("unexpected '{}'", keyword))),
+            _ => {
+                let words = self.words();
+                if words.is_empty() {
+                    return Err(self.error_here("expected a command"));
+                }
+                Ok(Statement::Command(words))
+            }
+        }
+    }
+
+    fn if_statement(&mut self) -> Result<Statement, SyntaxError> {
+        self.pos += 1;
+        let condition = self.condition()?;
+        let then = self.block()?;
+
+        let mut otherwise = Vec::new();
+        if matches!(self.peek_word(), Some(w) if w.is_bare("else")) {
+            self.pos += 1;
+            if matches!(self.peek_word(), Some(w) if w.is_bare("if")) {
+                otherwise.push(self.if_statement()?);
+            } else {
+                otherwise = self.block()?;
+            }
+        }
+
+        Ok(Statement::If {
+            condition,
+            then,
+            otherwise,
+        })
+    }
+
+    fn condition(&mut self) -> Result<Condition, SyntaxError> {
+        let negated = matches!(self.peek_word(), Some(w) if w.is_bare("!"));
+        if negated {
+            self.pos += 1;
+        }
+
+        let command = self.words();
+        if command.is_empty() {
+            return Err(self.error_here("expected a condition command"));
+        }
+        Ok(Condition { negated, command })
+    }
+
+    /// a single unquoted word that is a valid name
+    fn name(&mut self, what: &str) -> Result<String, SyntaxError> {
+        let name = self
+            .peek_word()
+            .and_then(|w| w.as_bare())
+            .filter(|n| is_name(n) && !KEYWORDS.contains(n))
+            .map(String::from);
+
+        match name {
+            Some(name) => {
+                self.pos += 1;
+                Ok(name)
+            }
+            None => Err(self.error_here(&format!("expected {}", what))),
+        }
+    }
+
+    /// consecutive words, up to the next separator or brace
+    fn words(&mut self) -> Vec<Word> {
+        let mut words = Vec::new();
+        while let Some(word) = self.peek_word() {
+            words.push(word.clone());
+            self.pos += 1;
+        }
+        words
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        && !text.starts_with('-')
+}
+
+impl Shell {
+    /// Parse and run text, which may hold several statements and blocks.
+    /// Errors from individual commands are printed and recorded in the
+    /// status variable; only syntax errors and failures to define a function
+    /// are returned.
+    pub fn execute(&self, text: &str, context: &mut Context) -> Result<ReturnCode, Box<dyn Error>> {
+        let statements = parse(text)?;
+        self.run_statements(&statements, context)
+    }
+
+    /// Run statements in order, stopping early only on ReturnCode::Abort
+    pub fn run_statements(
+        &self,
+        statements: &[Statement],
+        context: &mut Context,
+    ) -> Result<ReturnCode, Box<dyn Error>> {
+        let mut code = ReturnCode::Ok;
+        for statement in statements {
+            code = self.run_statement(statement, context)?;
+            if code == ReturnCode::Abort {
+                break;
+            }
+        }
+        Ok(code)
+    }
+
+    fn run_statement(
+        &self,
+        statement: &Statement,
+        context: &mut Context,
+    ) -> Result<ReturnCode, Box<dyn Error>> {
+        match statement {
+            Statement::Command(words) => Ok(self.run_words(words, context)),
+            Statement::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let (passed, code) = self.test_condition(condition, context);
+                if code == ReturnCode::Abort {
+                    return Ok(code);
+                }
+                if passed {
+                    self.run_statements(then, context)
+                } else {
+                    self.run_statements(otherwise, context)
+                }
+            }
+            Statement::While { condition, body } => {
+                let mut code = ReturnCode::Ok;
+                loop {
+                    let (passed, condition_code) = self.test_condition(condition, context);
+                    if condition_code == ReturnCode::Abort {
+                        return Ok(condition_code);
+                    }
+                    if !passed {
+                        return Ok(code);
+                    }
+                    code = self.run_statements(body, context)?;
+                    if code == ReturnCode::Abort {
+                        return Ok(code);
+                    }
+                }
+            }
+            Statement::For {
+                variable,
+                items,
+                body,
+            } => {
+                let mut code = ReturnCode::Ok;
+                for item in expand_words(items, context)? {
+                    context.insert(variable.clone(), item);
+                    code = self.run_statements(body, context)?;
+                    if code == ReturnCode::Abort {
+                        break;
+                    }
+                }
+                Ok(code)
+            }
+            Statement::Function { name, body } => {
+                self.define_function(name, Arc::clone(body))?;
+                Ok(ReturnCode::Ok)
+            }
+        }
+    }
+
+    /// Register a function as a command. Functions may be redefined, but
+    /// other commands may not be replaced.
+    pub fn define_function(
+        &self,
+        name: &str,
+        body: Arc<Vec<Statement>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let is_function = self.functions.read().unwrap().contains_key(name);
+        if !is_function && self.find_command_config(name).is_some() {
+            return Err(Box::new(CommandExistsError(name.into())));
+        }
+
+        let config = command::Config::new(name, FlagSpecSet::new(), FUNCTION_HELP, run_function);
+        self.register_command(config)?;
+        self.functions.write().unwrap().insert(name.into(), body);
+        Ok(())
+    }
+
+    /// the statements making up a function defined with `fn`
+    pub fn function_body(&self, name: &str) -> Option<Arc<Vec<Statement>>> {
+        self.functions.read().unwrap().get(name).cloned()
+    }
+
+    /// Expand and run a single command, printing any error. The outcome is
+    /// stored in the status variable.
+    fn run_words(&self, words: &[Word], context: &mut Context) -> ReturnCode {
+        match self.expand_command_words(words, context) {
+            Ok(args) => self.run_command(args, context),
+            Err(error) => finish_command(Err(error), context),
+        }
+    }
+
+    /// Run a command whose words are already expanded, e.g. the operands of
+    /// `time` or `repeat`, the way a command line is run: after alias
+    /// expansion, printing any error and storing the outcome in the status
+    /// variable.
+    pub fn run_command(&self, args: Vec<String>, context: &mut Context) -> ReturnCode {
+        let result = self
+            .expand_aliases(args, context)
+            .and_then(|args| self.run_args(&args, context));
+        finish_command(result, context)
+    }
+
+    /// Expand the words of a command line. Operands are globbed unless the
+    /// command, possibly behind an alias, is configured not to; flags never
+    /// are.
+    fn expand_command_words(
+        &self,
+        words: &[Word],
+        context: &Context,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut args = match words.first() {
+            Some(word) => word.expand_args(context),
+            None => return Ok(Vec::new()),
+        };
+
+        let glob = match self.expand_aliases(args.clone(), context)?.first() {
+            Some(name) => self
+                .find_command_config(name)
+                .is_none_or(|c| c.globs_operands()),
+            None => true,
+        };
+
+        for word in &words[1..] {
+            let is_flag = word.expand(context).starts_with('-');
+            if glob && !is_flag {
+                args.extend(glob_word(word, context)?);
+            } else {
+                args.extend(word.expand_args(context));
+            }
+        }
+        Ok(args)
+    }
+
+    fn test_condition(&self, condition: &Condition, context: &mut Context) -> (bool, ReturnCode) {
+        let code = self.run_words(&condition.command, context);
+        ((code.status() == 0) != condition.negated, code)
+    }
+}
+
+/// Print the error of a command, if it failed with one, and store its status
+fn finish_command(result: Result<ReturnCode, Box<dyn Error>>, context: &mut Context) -> ReturnCode {
+    let code = match result {
+        Ok(code) => code,
+        Err(error) => {
+            shell_println!("{}", error);
+            ReturnCode::Failure(1)
+        }
+    };
+
+    context.insert(lexer::STATUS_VARIABLE.into(), code.status().to_string());
+    code
+}
+
+/// Expand the item list of a for loop, globbing every word
+fn expand_words(words: &[Word], context: &Context) -> Result<Vec<String>, glob::NoMatchError> {
+    let mut expanded = Vec::new();
+    for word in words {
+        expanded.extend(glob_word(word, context)?);
+    }
+    Ok(expanded)
+}
+
+/// Expand a word, replacing it by the files it matches if it holds an
+/// unquoted wildcard
+fn glob_word(word: &Word, context: &Context) -> Result<Vec<String>, glob::NoMatchError> {
+    if let Some(pattern) = word.glob_pattern(context) {
+        let matches = glob::expand(&pattern);
+        if !matches.is_empty() {
+            return Ok(matches);
+        }
+        if context.get(GLOB_NOMATCH_VARIABLE).map(String::as_str) == Some(GLOB_NOMATCH_ERROR) {
+            return Err(glob::NoMatchError(word.expand(context)));
+        }
+    }
+    Ok(word.expand_args(context))
+}
+
+/// Callback shared by every function defined with `fn`. The operands are
+/// bound to the positional variables for the duration of the call.
+fn run_function(
+    command: &Command,
+    shell: &Shell,
+    context: &mut Context,
+) -> Result<ReturnCode, Box<dyn Error>> {
+    let name = command.config().name();
+    let body = match shell.function_body(name) {
+        Some(body) => body,
+        None => return Err(Box::new(super::UnknownCommandError(name.into()))),
+    };
+
+    let depth = CALL_DEPTH.with(|d| d.get());
+    if depth >= MAX_CALL_DEPTH {
+        return Err(Box::new(RecursionLimitError(name.into())));
+    }
+
+    let args: Vec<String> = command
+        .operands()
+        .iter()
+        .map(|o| o.value().to_string())
+        .collect();
+    let saved = bind_positional_args(&args, context);
+
+    CALL_DEPTH.with(|d| d.set(depth + 1));
+    let result = shell.run_statements(&body, context);
+    CALL_DEPTH.with(|d| d.set(depth));
+
+    for (key, value) in saved {
+        match value {
+            Some(value) => context.insert(key, value),
+            None => context.remove(&key),
+        };
+    }
+    result
+}
+
+/// Set $1.., $# and $@ to args, returning the previous values so they can
+/// be restored afterwards.
+fn bind_positional_args(args: &[String], context: &mut Context) -> Vec<(String, Option<String>)> {
+    let previous_count = lexer::positional_args(context).len();
+    let mut keys = vec![
+        lexer::ARG_COUNT_VARIABLE.to_string(),
+        lexer::ALL_ARGS_VARIABLE.to_string(),
+    ];
+    keys.extend((1..=std::cmp::max(previous_count, args.len())).map(|n| n.to_string()));
+
+    let saved = keys
+        .iter()
+        .map(|k| (k.clone(), context.get(k).cloned()))
+        .collect();
+
+    for (n, key) in keys.iter().skip(2).enumerate() {
+        match args.get(n) {
+            Some(arg) => context.insert(key.clone(), arg.clone()),
+            None => context.remove(key),
+        };
+    }
+    context.insert(lexer::ARG_COUNT_VARIABLE.into(), args.len().to_string());
+    context.insert(lexer::ALL_ARGS_VARIABLE.into(), args.join(" "));
+
+    saved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structure() {
+        let statements = parse("fn greet {\n  if ! check $1 { a } else if b { c } else { d }\n}\nfor f in *.rs { e $f }").unwrap();
+        assert_eq!(2, statements.len());
+        assert!(
+            matches!(&statements[0], Statement::Function { name, body } if name == "greet" && body.len() == 1)
+        );
+        assert!(
+            matches!(&statements[1], Statement::For { variable, items, .. } if variable == "f" && items.len() == 1)
+        );
+    }
+
+    #[test]
+    fn incomplete() {
+        let error = parse("while a {\n  b").unwrap_err();
+        assert!(error.incomplete);
+        assert_eq!((2, 4), (error.line, error.column));
+
+        let error = parse("a }").unwrap_err();
+        assert!(!error.incomplete);
+        assert_eq!((1, 3), (error.line, error.column));
+
+        assert!(!parse("fn if { a }").unwrap_err().incomplete);
+    }
+
+    #[test]
+    fn globbing() {
+        let dir = std::env::temp_dir().join(format!("cli-glob-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for file in ["b.rs", "a.rs", "c.txt", ".hidden.rs", "sub/d.rs"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let path = |name: &str| format!("{}/{}", dir.display(), name);
+        let words = |text: &str| match &lexer::tokenize(text).unwrap()[0].kind {
+            TokenKind::Word(word) => word.clone(),
+            _ => panic!("not a word"),
+        };
+        let mut context = Context::new();
+
+        assert_eq!(
+            vec![path("a.rs"), path("b.rs")],
+            glob_word(&words(&path("*.rs")), &context).unwrap()
+        );
+        assert_eq!(
+            vec![path("a.rs"), path("b.rs"), path("sub/d.rs")],
+            glob_word(&words(&path("**/?.rs")), &context).unwrap()
+        );
+        assert_eq!(
+            vec![path("*.rs")],
+            glob_word(&words(&path("'*.rs'")), &context).unwrap()
+        );
+
+        let missing = path("*.nothing");
+        assert_eq!(
+            vec![missing.clone()],
+            glob_word(&words(&missing), &context).unwrap()
+        );
+        context.insert(GLOB_NOMATCH_VARIABLE.into(), GLOB_NOMATCH_ERROR.into());
+        assert!(glob_word(&words(&missing), &context).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // only wildcards typed into the word make it a pattern
+        context.insert(lexer::STATUS_VARIABLE.into(), "0".into());
+        context.insert("star".into(), "*".into());
+        assert_eq!(None, words("$?").glob_pattern(&context));
+        assert_eq!(None, words("$star").glob_pattern(&context));
+        assert_eq!(vec!["*"], glob_word(&words("$star"), &context).unwrap());
+        assert_eq!(
+            Some("\\*/?".to_string()),
+            words("$star/?").glob_pattern(&context)
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Serving a Shell to clients over a TCP or Unix socket, e.g. as the admin
+//! console of a daemon.
+//!
+//! Every client runs in its own session on its own thread, with a copy of
+//! the server's initial Context, its own history and therefore its own
+//! prompt, while the commands, functions and aliases of the Shell are shared.
+//! The rc file of the Shell is not run for each client; run Shell::load_rc
+//! on the Context given to the server instead.
+use super::{Context, Session, Shell};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+/// default number of clients served at the same time
+const DEFAULT_MAX_CONNECTIONS: usize = 8;
+
+/// default time a client may stay silent before it is disconnected
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A stream a session can run over
+pub trait Connection: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn try_clone(&self) -> io::Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn try_clone(&self) -> io::Result<UnixStream> {
+        UnixStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Accepts clients and runs a session of a Shell for each of them
+pub struct Server {
+    max_connections: usize,
+    idle_timeout: Option<Duration>,
+    context: Context,
+    active: AtomicUsize,
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server {
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            context: Context::new(),
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// Clients connecting while this many are served are turned away
+    pub fn max_connections(mut self, max_connections: usize) -> Server {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Disconnect clients that send nothing for this long, or never with None
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Server {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// The Context every client starts with
+    pub fn context(mut self, context: Context) -> Server {
+        self.context = context;
+        self
+    }
+
+    /// number of clients being served right now
+    pub fn active_connections(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Serve the shell on a TCP address until accepting fails
+    pub fn serve_tcp<A: ToSocketAddrs>(&self, shell: &Shell, address: A) -> io::Result<()> {
+        self.serve(shell, TcpListener::bind(address)?.incoming())
+    }
+
+    /// Serve the shell on a Unix socket until accepting fails
+    #[cfg(unix)]
+    pub fn serve_unix<P: AsRef<Path>>(&self, shell: &Shell, path: P) -> io::Result<()> {
+        self.serve(shell, UnixListener::bind(path)?.incoming())
+    }
+
+    /// Serve the shell to every connection from incoming, returning once it
+    /// runs out or fails and every client has left.
+    pub fn serve<C, I>(&self, shell: &Shell, incoming: I) -> io::Result<()>
+    where
+        C: Connection,
+        I: Iterator<Item = io::Result<C>>,
+    {
+        thread::scope(|scope| {
+            for connection in incoming {
+                let mut connection = connection?;
+
+                if self.active.fetch_add(1, Ordering::SeqCst) >= self.max_connections {
+                    self.active.fetch_sub(1, Ordering::SeqCst);
+                    let _ = writeln!(connection, "Error: too many connections, try again later");
+                    continue;
+                }
+
+                scope.spawn(move || {
+                    if let Err(error) = self.serve_client(shell, connection) {
+                        eprintln!("Error: shell session failed: {}", error);
+                    }
+                    self.active.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Ok(())
+        })
+    }
+
+    fn serve_client<C: Connection>(&self, shell: &Shell, connection: C) -> io::Result<()> {
+        connection.set_read_timeout(self.idle_timeout)?;
+        let reader = BufReader::new(connection.try_clone()?);
+        let mut session = Session::new(reader, connection);
+        let mut context = self.context.clone();
+        shell.run_session(&mut session, &mut context);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::CommandSet;
+    use std::io::BufRead;
+
+    #[test]
+    fn sessions() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let shell = Shell::new(CommandSet::new(), "");
+        let server = Server::new().max_connections(1);
+
+        thread::scope(|scope| {
+            // serve exactly three connections, then stop accepting
+            let incoming = listener.incoming().take(3);
+            let serving = scope.spawn(|| server.serve(&shell, incoming));
+
+            let mut first = TcpStream::connect(address).unwrap();
+            let mut reader = BufReader::new(first.try_clone().unwrap());
+            let mut line = String::new();
+            first.write_all(b"nothing\n").unwrap();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!("#> Error: unknown command nothing\n", line);
+
+            let mut second = String::new();
+            let mut refused = TcpStream::connect(address).unwrap();
+            refused.read_to_string(&mut second).unwrap();
+            assert!(second.contains("too many connections"));
+
+            drop(reader);
+            drop(first);
+            while server.active_connections() > 0 {
+                thread::sleep(Duration::from_millis(10));
+            }
+
+            let mut third = TcpStream::connect(address).unwrap();
+            third.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut goodbye = String::new();
+            third.read_to_string(&mut goodbye).unwrap();
+            assert!(goodbye.contains("Goodbye."));
+
+            serving.join().unwrap().unwrap();
+        });
+    }
+}
@@ -0,0 +1,280 @@
+//! Sessions: one user talking to a Shell through a reader and a writer.
+//!
+//! The interactive shell is a session over stdin and stdout, and every client
+//! of a served shell (see the server module) gets a session over its
+//! connection. While a session runs, everything the shell and its commands
+//! print goes to the session's writer, so commands should print with
+//! `shell_print!` / `shell_println!` rather than `print!` / `println!`.
+//! Outside of a session these print to stdout. Error output, such as the
+//! stderr of external commands, goes to the session's error writer if it has
+//! one, and to stderr outside of a session.
+use super::macros::Macro;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+thread_local! {
+    /// output, history and recording of the session running on this thread
+    static CURRENT: RefCell<Option<SessionState>> = const { RefCell::new(None) };
+}
+
+/// Print to the output of the current session, like print!
+#[macro_export]
+macro_rules! shell_print {
+    ($($arg:tt)*) => {
+        $crate::shell::session::write_output(format_args!($($arg)*))
+    };
+}
+
+/// Print a line to the output of the current session, like println!
+#[macro_export]
+macro_rules! shell_println {
+    () => {
+        $crate::shell_print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::shell_print!("{}\n", format_args!($($arg)*))
+    };
+}
+
+/// the parts of a session that commands reach through the thread
+struct SessionState {
+    writer: Box<dyn Write>,
+    /// where error output goes; None sends it to the writer
+    errors: Option<Box<dyn Write>>,
+    history: Vec<String>,
+    /// the macro this session is recording, if any
+    recording: Option<Macro>,
+    terminal: bool,
+    /// whether the session reads the process's stdin, which external
+    /// commands may then read too
+    stdin: bool,
+}
+
+/// A reader to take command lines from and a writer for everything printed
+pub struct Session<R: BufRead> {
+    reader: R,
+    state: Option<SessionState>,
+}
+
+impl<R: BufRead> Session<R> {
+    pub fn new<W: Write + 'static>(reader: R, writer: W) -> Session<R> {
+        Session {
+            reader,
+            state: Some(SessionState {
+                writer: Box::new(writer),
+                errors: None,
+                history: Vec::new(),
+                recording: None,
+                terminal: false,
+                stdin: false,
+            }),
+        }
+    }
+
+    /// Mark the writer as a terminal, so that the prompt may use colors
+    pub fn terminal(mut self, terminal: bool) -> Session<R> {
+        if let Some(ref mut state) = self.state {
+            state.terminal = terminal;
+        }
+        self
+    }
+
+    /// Send error output, e.g. the stderr of external commands, to its own
+    /// writer instead of the session's writer
+    pub fn errors<W: Write + 'static>(mut self, errors: W) -> Session<R> {
+        if let Some(ref mut state) = self.state {
+            state.errors = Some(Box::new(errors));
+        }
+        self
+    }
+
+    /// the command lines entered so far, oldest first
+    pub fn history(&self) -> Vec<String> {
+        match self.state {
+            Some(ref state) => state.history.clone(),
+            None => history(),
+        }
+    }
+
+    pub(crate) fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        self.reader.read_line(buf)
+    }
+
+    /// Make this session the current one on this thread until the returned
+    /// guard is dropped.
+    pub(crate) fn enter(&mut self) -> SessionGuard<'_, R> {
+        let state = self.state.take();
+        let previous = CURRENT.with(|c| c.replace(state));
+        SessionGuard {
+            session: self,
+            previous,
+        }
+    }
+}
+
+/// The interactive session on stdin and stdout
+pub fn stdio() -> Session<io::StdinLock<'static>> {
+    let mut session = Session::new(io::stdin().lock(), io::stdout())
+        .errors(io::stderr())
+        .terminal(io::stdout().is_terminal());
+    if let Some(ref mut state) = session.state {
+        state.stdin = true;
+    }
+    session
+}
+
+/// Gives the session state back when the session stops running
+pub(crate) struct SessionGuard<'a, R: BufRead> {
+    session: &'a mut Session<R>,
+    previous: Option<SessionState>,
+}
+
+impl<R: BufRead> SessionGuard<'_, R> {
+    pub(crate) fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        self.session.read_line(buf)
+    }
+}
+
+impl<R: BufRead> Drop for SessionGuard<'_, R> {
+    fn drop(&mut self) {
+        self.session.state = CURRENT.with(|c| c.replace(self.previous.take()));
+    }
+}
+
+/// Write to the current session, or stdout when there is none
+pub fn write_output(args: fmt::Arguments) {
+    let written = CURRENT.with(|c| match *c.borrow_mut() {
+        Some(ref mut state) => Some(state.writer.write_fmt(args)),
+        None => None,
+    });
+    match written {
+        // the client is gone; the read loop notices and ends the session
+        Some(_) => (),
+        None => print!("{}", args),
+    }
+}
+
+/// Write raw bytes to the current session, or stdout when there is none
+pub fn write_output_bytes(bytes: &[u8]) -> io::Result<()> {
+    let written = CURRENT.with(|c| match *c.borrow_mut() {
+        Some(ref mut state) => Some(state.writer.write_all(bytes)),
+        None => None,
+    });
+    match written {
+        Some(result) => result,
+        None => io::stdout().write_all(bytes),
+    }
+}
+
+/// Write raw bytes to the error output of the current session, or stderr
+/// when there is none
+pub fn write_error_bytes(bytes: &[u8]) -> io::Result<()> {
+    let written = CURRENT.with(|c| match *c.borrow_mut() {
+        Some(ref mut state) => match state.errors {
+            Some(ref mut errors) => Some(errors.write_all(bytes)),
+            None => Some(state.writer.write_all(bytes)),
+        },
+        None => None,
+    });
+    match written {
+        Some(result) => result,
+        None => io::stderr().write_all(bytes),
+    }
+}
+
+pub fn flush_output() {
+    let flushed = CURRENT.with(|c| match *c.borrow_mut() {
+        Some(ref mut state) => {
+            if let Some(ref mut errors) = state.errors {
+                let _ = errors.flush();
+            }
+            Some(state.writer.flush())
+        }
+        None => None,
+    });
+    if flushed.is_none() {
+        let _ = io::stdout().flush();
+    }
+}
+
+/// whether the current session writes to a terminal
+pub fn is_terminal() -> bool {
+    CURRENT.with(|c| match *c.borrow() {
+        Some(ref state) => state.terminal,
+        None => io::stdout().is_terminal(),
+    })
+}
+
+/// whether the current session reads the process's stdin; true outside of
+/// a session
+pub fn reads_stdin() -> bool {
+    CURRENT.with(|c| match *c.borrow() {
+        Some(ref state) => state.stdin,
+        None => true,
+    })
+}
+
+/// Add a command line to the history of the current session
+pub fn add_history(line: &str) {
+    CURRENT.with(|c| {
+        if let Some(ref mut state) = *c.borrow_mut() {
+            state.history.push(line.into());
+        }
+    });
+}
+
+/// the history of the current session
+pub fn history() -> Vec<String> {
+    CURRENT.with(|c| match *c.borrow() {
+        Some(ref state) => state.history.clone(),
+        None => Vec::new(),
+    })
+}
+
+/// Work on the macro recording of the current session, or return None when
+/// there is no session
+pub(crate) fn with_recording<T, F: FnOnce(&mut Option<Macro>) -> T>(f: F) -> Option<T> {
+    CURRENT.with(|c| c.borrow_mut().as_mut().map(|state| f(&mut state.recording)))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// a writer that can still be read after the session owns it
+    #[derive(Clone, Default)]
+    pub(crate) struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Buffer {
+        pub(crate) fn text(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into()
+        }
+    }
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_and_history() {
+        let buffer = Buffer::default();
+        let mut session = Session::new(io::empty(), buffer.clone());
+        {
+            let _guard = session.enter();
+            crate::shell_println!("hello {}", 1);
+            add_history("a");
+            assert_eq!(vec!["a"], history());
+        }
+        assert!(history().is_empty());
+        assert_eq!(vec!["a"], session.history());
+        assert_eq!("hello 1\n", buffer.text());
+    }
+}
@@ -0,0 +1,242 @@
+//! Compares job throughput of the work-stealing ThreadPool with the
+//! channel-based design it replaced.
+//!
+//! Usage: pool_bench [jobs] [threads...]
+//!
+//! Two workloads are run for each thread count: many small jobs submitted
+//! from the main thread, and jobs that submit small jobs of their own.
+use std::env;
+use std::hint::black_box;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use webserver::ThreadPool;
+
+/// jobs per workload when none are given
+const DEFAULT_JOBS: usize = 200_000;
+
+/// thread counts tried when none are given
+const DEFAULT_THREADS: [usize; 4] = [1, 2, 4, 8];
+
+/// small jobs submitted by each parent job in the nested workload
+const CHILDREN: usize = 100;
+
+/// The ThreadPool before work stealing: every worker takes jobs from one
+/// channel behind a mutex.
+mod channel_pool {
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+
+    type Job = Box<dyn FnOnce() + Send + 'static>;
+
+    pub struct ThreadPool {
+        workers: Vec<thread::JoinHandle<()>>,
+        sender: Option<mpsc::Sender<Job>>,
+    }
+
+    impl ThreadPool {
+        pub fn new(size: usize) -> ThreadPool {
+            let (tx, rx) = mpsc::channel::<Job>();
+            let rx = Arc::new(Mutex::new(rx));
+            let workers = (0..size)
+                .map(|_| {
+                    let rx = Arc::clone(&rx);
+                    thread::spawn(move || loop {
+                        let message = rx.lock().unwrap().recv();
+                        match message {
+                            Ok(job) => job(),
+                            Err(_) => break,
+                        }
+                    })
+                })
+                .collect();
+
+            ThreadPool {
+                workers,
+                sender: Some(tx),
+            }
+        }
+
+        pub fn execute<F>(&self, f: F)
+        where
+            F: FnOnce() + Send + 'static,
+        {
+            self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+        }
+    }
+
+    impl Drop for ThreadPool {
+        fn drop(&mut self) {
+            drop(self.sender.take());
+            for worker in self.workers.drain(..) {
+                worker.join().unwrap();
+            }
+        }
+    }
+}
+
+/// What the workloads need from a pool
+trait Pool: Send + Sync + 'static {
+    fn spawn(&self, job: Box<dyn FnOnce() + Send + 'static>);
+}
+
+impl Pool for ThreadPool {
+    fn spawn(&self, job: Box<dyn FnOnce() + Send + 'static>) {
+        self.execute(job);
+    }
+}
+
+impl Pool for channel_pool::ThreadPool {
+    fn spawn(&self, job: Box<dyn FnOnce() + Send + 'static>) {
+        self.execute(job);
+    }
+}
+
+/// a few hundred nanoseconds of work
+fn small_job() {
+    let mut sum = 0u64;
+    for i in 0..black_box(200u64) {
+        sum = sum.wrapping_add(i * i);
+    }
+    black_box(sum);
+}
+
+/// Counts finished jobs and signals when the last one is done
+#[derive(Clone)]
+struct Latch {
+    done: Arc<AtomicUsize>,
+    total: usize,
+    tx: mpsc::Sender<()>,
+}
+
+impl Latch {
+    fn new(total: usize) -> (Latch, mpsc::Receiver<()>) {
+        let (tx, rx) = mpsc::channel();
+        let latch = Latch {
+            done: Arc::new(AtomicUsize::new(0)),
+            total,
+            tx,
+        };
+        (latch, rx)
+    }
+
+    fn count(&self) {
+        if self.done.fetch_add(1, Ordering::SeqCst) + 1 == self.total {
+            self.tx.send(()).unwrap();
+        }
+    }
+}
+
+/// Submit jobs one by one from the main thread. Returns how many small
+/// jobs ran and how long they took.
+fn flat<P: Pool>(pool: &Arc<P>, jobs: usize) -> (usize, Duration) {
+    let (latch, finished) = Latch::new(jobs);
+    let start = Instant::now();
+    for _ in 0..jobs {
+        let latch = latch.clone();
+        pool.spawn(Box::new(move || {
+            small_job();
+            latch.count();
+        }));
+    }
+    finished.recv().unwrap();
+    (jobs, start.elapsed())
+}
+
+/// Submit parent jobs that each submit CHILDREN small jobs, enough of them
+/// for at least the given number of small jobs. Returns how many small jobs
+/// ran and how long they took.
+fn nested<P: Pool>(pool: &Arc<P>, jobs: usize) -> (usize, Duration) {
+    let parents = jobs.div_ceil(CHILDREN);
+    let total = parents * CHILDREN;
+    let (latch, finished) = Latch::new(total);
+    let start = Instant::now();
+    for _ in 0..parents {
+        let (inner, latch) = (Arc::clone(pool), latch.clone());
+        pool.spawn(Box::new(move || {
+            for _ in 0..CHILDREN {
+                let latch = latch.clone();
+                inner.spawn(Box::new(move || {
+                    small_job();
+                    latch.count();
+                }));
+            }
+        }));
+    }
+    finished.recv().unwrap();
+    let elapsed = start.elapsed();
+
+    // parent jobs may still hold the pool for a moment; it must not be
+    // dropped on one of its own threads
+    while Arc::strong_count(pool) > 1 {
+        thread::yield_now();
+    }
+    (total, elapsed)
+}
+
+fn jobs_per_second(jobs: usize, elapsed: Duration) -> f64 {
+    jobs as f64 / elapsed.as_secs_f64()
+}
+
+fn parse_args() -> Result<(usize, Vec<usize>), String> {
+    let mut args = env::args().skip(1).map(|a| {
+        a.parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or(format!("'{}' is not a positive number", a))
+    });
+    let jobs = args.next().transpose()?.unwrap_or(DEFAULT_JOBS);
+    let threads = args.collect::<Result<Vec<usize>, String>>()?;
+    match threads.is_empty() {
+        true => Ok((jobs, DEFAULT_THREADS.to_vec())),
+        false => Ok((jobs, threads)),
+    }
+}
+
+fn main() {
+    let (jobs, thread_counts) = match parse_args() {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("Error: {}\nUsage: pool_bench [jobs] [threads...]", error);
+            process::exit(1);
+        }
+    };
+
+    // the pools print as they shut down, so the table comes at the end
+    let mut rows = Vec::new();
+    for threads in thread_counts {
+        let channel = Arc::new(channel_pool::ThreadPool::new(threads));
+        let stealing = Arc::new(ThreadPool::new(threads));
+        rows.push(("flat", threads, flat(&channel, jobs), flat(&stealing, jobs)));
+        rows.push((
+            "nested",
+            threads,
+            nested(&channel, jobs),
+            nested(&stealing, jobs),
+        ));
+        drop(channel);
+        drop(stealing);
+    }
+
+    println!(
+        "{:<8} {:>7} {:>9} {:>16} {:>16} {:>8}",
+        "workload", "threads", "jobs", "channel jobs/s", "stealing jobs/s", "speedup"
+    );
+    for (name, threads, (count, channel), (_, stealing)) in rows {
+        let (channel, stealing) = (
+            jobs_per_second(count, channel),
+            jobs_per_second(count, stealing),
+        );
+        println!(
+            "{:<8} {:>7} {:>9} {:>16.0} {:>16.0} {:>7.2}x",
+            name,
+            threads,
+            count,
+            channel,
+            stealing,
+            stealing / channel
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use crate::request::{ParseError, Request};
+use crate::response::Response;
+use crate::router::Router;
+use crate::shutdown::ShutdownHandle;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Limits on how long a persistent connection is kept open
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConnectionLimits {
+    idle_timeout: Duration,
+    max_requests: usize,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> ConnectionLimits {
+        ConnectionLimits {
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 100,
+        }
+    }
+}
+
+impl ConnectionLimits {
+    pub fn new() -> ConnectionLimits {
+        ConnectionLimits::default()
+    }
+
+    /// how long to wait for the next request before closing the connection
+    pub fn idle_timeout(mut self, timeout: Duration) -> ConnectionLimits {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// most requests answered on one connection; 1 turns keep-alive off
+    ///
+    /// # Panics
+    ///
+    /// Panics if max is zero.
+    pub fn max_requests(mut self, max: usize) -> ConnectionLimits {
+        assert!(max > 0);
+        self.max_requests = max;
+        self
+    }
+
+    pub fn get_idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    pub fn get_max_requests(&self) -> usize {
+        self.max_requests
+    }
+}
+
+/// Answer requests on a TCP connection until the client closes it, asks to
+/// close it, goes idle, reaches the request limit or the server shuts down.
+pub fn serve_stream(
+    stream: &TcpStream,
+    router: &Router,
+    limits: &ConnectionLimits,
+    shutdown: &ShutdownHandle,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(limits.idle_timeout))?;
+    let mut reader = BufReader::new(stream);
+    let mut writer = stream;
+    serve_requests(&mut reader, &mut writer, router, limits, shutdown)
+}
+
+/// Read requests one after another and write each response before reading
+/// the next, so pipelined requests are answered in order.
+pub fn serve_requests<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    router: &Router,
+    limits: &ConnectionLimits,
+    shutdown: &ShutdownHandle,
+) -> io::Result<()> {
+    for count in 1.. {
+        let request = match Request::read_from(reader) {
+            Ok(request) => request,
+            Err(ParseError::Closed) => return Ok(()),
+            Err(ParseError::Io(error)) => {
+                return match error.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Ok(()),
+                    _ => Err(error),
+                };
+            }
+            // the rest of the stream can't be trusted after a bad request
+            Err(error) => {
+                return Response::text(error.status(), &format!("{}\n", error))
+                    .with_header("Connection", "close")
+                    .write_to(writer);
+            }
+        };
+
+        let mut response = router.handle(&request);
+        // HTTP/1.0 has no chunked transfer encoding
+        if request.version() == "HTTP/1.0" {
+            response = response.into_buffered()?;
+        }
+        let keep_alive = count < limits.max_requests
+            && !shutdown.is_shutdown()
+            && wants_keep_alive(&request)
+            && !has_token(response.headers().get_all("Connection"), "close");
+        if keep_alive {
+            let remaining = limits.max_requests - count;
+            response.headers_mut().set("Connection", "keep-alive");
+            response.headers_mut().set(
+                "Keep-Alive",
+                &format!(
+                    "timeout={}, max={}",
+                    limits.idle_timeout.as_secs(),
+                    remaining
+                ),
+            );
+        } else {
+            response.headers_mut().set("Connection", "close");
+            response.headers_mut().remove("Keep-Alive");
+        }
+
+        match request.method() {
+            "HEAD" => response.write_head_to(writer)?,
+            _ => response.write_to(writer)?,
+        }
+        if !keep_alive {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// HTTP/1.1 connections persist unless the client sends `Connection: close`;
+/// HTTP/1.0 connections only persist with `Connection: keep-alive`.
+fn wants_keep_alive(request: &Request) -> bool {
+    let connection = request.headers().get_all("Connection");
+    match request.version() {
+        "HTTP/1.0" => has_token(connection, "keep-alive"),
+        _ => !has_token(connection, "close"),
+    }
+}
+
+/// whether a comma separated header value lists a token
+fn has_token<'a, I: Iterator<Item = &'a str>>(mut values: I, token: &str) -> bool {
+    values.any(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serve(input: &str, limits: ConnectionLimits) -> String {
+        let mut router = Router::new();
+        router.get("/:name", |_, params| {
+            Response::text(200, params.get("name").unwrap())
+        });
+
+        let mut output = Vec::new();
+        let shutdown = ShutdownHandle::new();
+        serve_requests(
+            &mut input.as_bytes(),
+            &mut output,
+            &router,
+            &limits,
+            &shutdown,
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn keep_alive() {
+        let output = serve(
+            "GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\nConnection: close\r\n\r\nGET /d HTTP/1.1\r\n\r\n",
+            ConnectionLimits::new(),
+        );
+        assert_eq!(3, output.matches("HTTP/1.1 200 OK").count());
+        assert!(output.find("\r\n\r\na").unwrap() < output.find("\r\n\r\nb").unwrap());
+        assert!(output.ends_with("Connection: close\r\nContent-Length: 1\r\n\r\nc"));
+
+        let output = serve(
+            "GET /a HTTP/1.0\r\n\r\nGET /b HTTP/1.0\r\n\r\n",
+            ConnectionLimits::new(),
+        );
+        assert_eq!(1, output.matches("200 OK").count());
+
+        let output = serve(
+            "GET /a HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\nGET /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\n\r\n",
+            ConnectionLimits::new().max_requests(2),
+        );
+        assert!(output.contains("Keep-Alive: timeout=5, max=1\r\n"));
+        assert_eq!(2, output.matches("200 OK").count());
+    }
+}
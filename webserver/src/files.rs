@@ -0,0 +1,160 @@
+use crate::response::Response;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// file served for a request naming a directory
+const INDEX_FILE: &str = "index.html";
+
+/// Serves the files under a document root
+#[derive(Clone, Debug)]
+pub struct StaticFiles {
+    root: PathBuf,
+}
+
+impl StaticFiles {
+    /// Serve files from root. The root must be an existing directory.
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<StaticFiles> {
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "document root is not a directory",
+            ));
+        }
+        Ok(StaticFiles { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Find the file for a decoded request path. Paths with '..' segments,
+    /// and paths that resolve outside the root through a symlink, are
+    /// refused with PermissionDenied.
+    pub fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let mut file = self.root.clone();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => (),
+                ".." => return Err(denied()),
+                s if s.contains(['\\', '\0']) => return Err(denied()),
+                s => file.push(s),
+            }
+        }
+
+        let mut file = fs::canonicalize(file)?;
+        if file.is_dir() {
+            file = fs::canonicalize(file.join(INDEX_FILE))?;
+        }
+        if !file.starts_with(&self.root) {
+            return Err(denied());
+        }
+        match file.is_file() {
+            true => Ok(file),
+            false => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "not a regular file",
+            )),
+        }
+    }
+
+    /// A response with the file for a decoded request path, or a 403 or 404
+    pub fn serve(&self, path: &str) -> Response {
+        self.serve_with_status(200, path)
+    }
+
+    /// Like serve, with another status for a file that is found
+    pub fn serve_with_status(&self, status: u16, path: &str) -> Response {
+        match self.resolve(path) {
+            Ok(file) => Response::new(status)
+                .with_header("Content-Type", mime_type(&file))
+                .with_file(file),
+            Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+                Response::text(403, "Forbidden\n")
+            }
+            Err(_) => Response::text(404, "Not Found\n"),
+        }
+    }
+}
+
+fn denied() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "path is outside the document root",
+    )
+}
+
+/// the Content-Type for a file, from its extension
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "wasm" => "application/wasm",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn resolve() {
+        let dir = env::temp_dir().join(format!("webserver-files-{}", std::process::id()));
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("docs/a.png"), [0x89, b'P', b'N', b'G']).unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("secret.txt"), root.join("link.txt")).unwrap();
+
+        let files = StaticFiles::new(&root).unwrap();
+        assert_eq!(files.root().join("index.html"), files.resolve("/").unwrap());
+        assert_eq!(
+            files.root().join("docs/a.png"),
+            files.resolve("/docs/./a.png").unwrap()
+        );
+        assert_eq!(
+            "image/png",
+            mime_type(&files.resolve("/docs/a.png").unwrap())
+        );
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            files.resolve("/docs").unwrap_err().kind()
+        );
+        assert_eq!(
+            io::ErrorKind::PermissionDenied,
+            files.resolve("/../secret.txt").unwrap_err().kind()
+        );
+        #[cfg(unix)]
+        assert_eq!(403, files.serve("/link.txt").status());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
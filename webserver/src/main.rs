@@ -1,41 +1,86 @@
-use std::fs;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::net::{TcpListener, TcpStream};
+use std::env;
+use std::process;
 use std::thread;
 use std::time::Duration;
-use webserver::ThreadPool;
+use webserver::shutdown::{self, ShutdownHandle};
+use webserver::{PoolConfig, Response, Router, Server, StaticFiles};
 
-fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
+/// served when no document root is given on the command line
+const DEFAULT_DOCUMENT_ROOT: &str = "public";
 
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
+/// the secret a client must send to shut the server down over HTTP; without
+/// it there is no shutdown route
+const ADMIN_TOKEN_VARIABLE: &str = "WEBSERVER_ADMIN_TOKEN";
 
-        pool.execute(|| {
-            handle_connection(stream);
-        });
+fn main() {
+    let root = env::args().nth(1).unwrap_or(DEFAULT_DOCUMENT_ROOT.into());
+    let files = match StaticFiles::new(&root) {
+        Ok(files) => files,
+        Err(error) => {
+            eprintln!("Cannot serve {}: {}", root, error);
+            process::exit(1);
+        }
+    };
+
+    let shutdown = ShutdownHandle::new();
+    if let Err(error) = shutdown::shutdown_on_signals(shutdown.clone()) {
+        eprintln!("Cannot handle signals: {}", error);
     }
 
-    println!("Shutting down.");
+    let pool = PoolConfig::elastic(2, 16)
+        .idle_timeout(Duration::from_secs(30))
+        .thread_name_prefix("worker");
+    let admin_token = env::var(ADMIN_TOKEN_VARIABLE)
+        .ok()
+        .filter(|token| !token.is_empty());
+    let server = Server::bind(
+        "127.0.0.1:7878",
+        routes(files, admin_token, shutdown.clone()),
+    )
+    .and_then(|server| server.with_shutdown(shutdown))
+    .map(|server| server.pool(pool));
+    let result = match server {
+        Ok(server) => server.run(),
+        Err(error) => Err(error),
+    };
+    if let Err(error) = result {
+        eprintln!("Server failed: {}", error);
+        process::exit(1);
+    }
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader.lines().next().unwrap().unwrap();
-
-    let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
-        "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "hello.html")
+fn routes(files: StaticFiles, admin_token: Option<String>, shutdown: ShutdownHandle) -> Router {
+    let mut router = Router::new();
+    let sleep_files = files.clone();
+    router.get("/sleep", move |_, _| {
+        thread::sleep(Duration::from_secs(5));
+        sleep_files.serve("/")
+    });
+    // any local user can reach the port, so shutting down takes the token
+    if let Some(token) = admin_token {
+        let expected = format!("Bearer {}", token);
+        router.post("/admin/shutdown", move |request, _| {
+            let given = request.headers().get("Authorization").unwrap_or("");
+            if !constant_time_eq(given.as_bytes(), expected.as_bytes()) {
+                return Response::text(401, "Unauthorized\n")
+                    .with_header("WWW-Authenticate", "Bearer");
+            }
+            shutdown.shutdown();
+            Response::text(202, "Shutting down\n")
+        });
+    }
+    router.get("/*path", move |_, params| {
+        let response = files.serve(params.get("path").unwrap_or(""));
+        match response.status() {
+            404 => files.serve_with_status(404, "/404.html"),
+            _ => response,
         }
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html"),
-    };
+    });
+    router
+}
 
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-    stream.write_all(response.as_bytes()).unwrap();
+/// Compare without returning early at the first difference, so the time taken
+/// doesn't tell how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
 }
@@ -0,0 +1,713 @@
+//! A thread pool that schedules by work stealing.
+//!
+//! Jobs submitted from outside the pool go to a global injector queue.
+//! Jobs submitted by a job go to the deque of the worker running it, which
+//! takes its newest job first. A worker with an empty deque takes a batch
+//! from the injector, and failing that steals the oldest half of another
+//! worker's deque, so workers rarely contend on one lock.
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
+use std::thread;
+use std::time::Duration;
+
+/// default time an extra thread waits for a job before it exits
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// most jobs a worker moves from the injector to its deque at once
+const MAX_BATCH: usize = 32;
+
+/// longest a caller blocked on a full queue sleeps before checking again
+const BLOCK_RECHECK: Duration = Duration::from_millis(50);
+
+/// What a bounded ThreadPool does with a job when its queue is full
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    /// wait until there is room in the queue
+    Block,
+    /// refuse the job with ExecuteError::Full
+    Reject,
+    /// run the job on the thread that submitted it
+    CallerRuns,
+}
+
+/// Why a job was not queued
+#[derive(Debug, PartialEq)]
+pub enum ExecuteError {
+    /// the queue is full and the policy is Reject
+    Full,
+}
+
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecuteError::Full => write!(f, "the job queue is full"),
+        }
+    }
+}
+
+impl Error for ExecuteError {}
+
+/// How many threads a ThreadPool runs, how they are made and how jobs wait
+/// for them
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolConfig {
+    min_threads: usize,
+    max_threads: usize,
+    idle_timeout: Duration,
+    thread_name_prefix: Option<String>,
+    stack_size: Option<usize>,
+    queue: Option<(usize, OverflowPolicy)>,
+}
+
+impl PoolConfig {
+    /// a fixed number of threads and an unbounded queue
+    pub fn new(size: usize) -> PoolConfig {
+        PoolConfig::elastic(size, size)
+    }
+
+    /// Start with min threads and add threads up to max while jobs are
+    /// waiting. Threads beyond min exit after the idle timeout.
+    pub fn elastic(min: usize, max: usize) -> PoolConfig {
+        PoolConfig {
+            min_threads: min,
+            max_threads: max,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            thread_name_prefix: None,
+            stack_size: None,
+            queue: None,
+        }
+    }
+
+    /// how long a thread beyond the minimum waits for a job before it exits
+    pub fn idle_timeout(mut self, timeout: Duration) -> PoolConfig {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// name threads `<prefix>-<id>`
+    pub fn thread_name_prefix(mut self, prefix: &str) -> PoolConfig {
+        self.thread_name_prefix = Some(prefix.into());
+        self
+    }
+
+    /// stack size of each thread, in bytes
+    pub fn stack_size(mut self, bytes: usize) -> PoolConfig {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// Queue at most capacity jobs waiting for a thread, and handle any more
+    /// by the policy. With a capacity of zero a job is only taken when a
+    /// thread is free.
+    pub fn queue(mut self, capacity: usize, policy: OverflowPolicy) -> PoolConfig {
+        self.queue = Some((capacity, policy));
+        self
+    }
+
+    pub fn get_min_threads(&self) -> usize {
+        self.min_threads
+    }
+
+    pub fn get_max_threads(&self) -> usize {
+        self.max_threads
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Called with the worker id and panic message when a job panics
+type PanicHook = Box<dyn Fn(usize, &str) + Send + Sync>;
+
+thread_local! {
+    /// the pool and deque of the worker running on this thread
+    static CURRENT_WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// The worker threads, changed only with the lock held
+#[derive(Default)]
+struct PoolState {
+    workers: Vec<Worker>,
+    /// deques no running worker owns
+    free_slots: Vec<usize>,
+    next_id: usize,
+    closed: bool,
+}
+
+struct Shared {
+    injector: Mutex<VecDeque<Job>>,
+    /// one deque for each thread the pool may run
+    locals: Vec<Mutex<VecDeque<Job>>>,
+    state: Mutex<PoolState>,
+    /// wakes sleeping workers
+    work: Condvar,
+    /// wakes callers blocked on a full queue
+    space: Condvar,
+    /// jobs submitted that no worker has started
+    queued: AtomicUsize,
+    /// jobs running now
+    active: AtomicUsize,
+    threads: AtomicUsize,
+    /// workers waiting for a job that no wakeup has been sent to
+    sleeping: AtomicUsize,
+    /// wakeups sent that no worker has taken yet
+    waking: AtomicUsize,
+    blocked: AtomicUsize,
+    panic_hook: RwLock<PanicHook>,
+    config: PoolConfig,
+}
+
+/// Lock a mutex whether or not it is poisoned. Jobs never run with one of
+/// the pool's locks held, so their panics can't leave its data half done.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Shared {
+    fn id(&self) -> usize {
+        self as *const Shared as usize
+    }
+
+    /// Count a job as queued if a bounded queue has room for it. Threads
+    /// that are free, or may still be started, take jobs without queueing.
+    fn try_reserve(&self, capacity: usize) -> bool {
+        let limit = capacity
+            + self
+                .config
+                .max_threads
+                .saturating_sub(self.active.load(SeqCst));
+        self.queued
+            .fetch_update(SeqCst, SeqCst, |queued| {
+                (queued < limit).then_some(queued + 1)
+            })
+            .is_ok()
+    }
+
+    fn wait_for_space(&self) {
+        let state = lock(&self.state);
+        self.blocked.fetch_add(1, SeqCst);
+        let _ = self.space.wait_timeout(state, BLOCK_RECHECK);
+        self.blocked.fetch_sub(1, SeqCst);
+    }
+
+    /// Queue a job on this thread's deque if it is one of our workers, or
+    /// on the injector
+    fn push(&self, job: Job) {
+        match CURRENT_WORKER.get() {
+            Some((pool, slot)) if pool == self.id() => lock(&self.locals[slot]).push_back(job),
+            _ => lock(&self.injector).push_back(job),
+        }
+    }
+
+    /// Wake a sleeping worker, or start one when jobs outnumber the threads
+    /// that are free
+    fn notify(self: &Arc<Shared>) {
+        // one wakeup at a time: the woken worker wakes the next if it finds
+        // more jobs, so a burst of submits doesn't wake every thread at once
+        if self.sleeping.load(SeqCst) > 0 {
+            if self.waking.load(SeqCst) > 0 {
+                return;
+            }
+            let _state = lock(&self.state);
+            if self.sleeping.load(SeqCst) > 0 && self.waking.load(SeqCst) == 0 {
+                self.sleeping.fetch_sub(1, SeqCst);
+                self.waking.fetch_add(1, SeqCst);
+                self.work.notify_one();
+                return;
+            }
+        }
+
+        let spare = |shared: &Shared| {
+            shared
+                .threads
+                .load(SeqCst)
+                .saturating_sub(shared.active.load(SeqCst))
+        };
+        if self.threads.load(SeqCst) < self.config.max_threads
+            && self.queued.load(SeqCst) > spare(self)
+        {
+            let mut state = lock(&self.state);
+            if self.threads.load(SeqCst) < self.config.max_threads
+                && self.queued.load(SeqCst) > spare(self)
+            {
+                if let Err(error) = Worker::spawn(self, &mut state) {
+                    println!("Failed to add a worker thread: {}", error);
+                }
+            }
+        }
+    }
+
+    /// the next job for the worker with this deque
+    fn find_job(&self, slot: usize) -> Option<Job> {
+        if let Some(job) = lock(&self.locals[slot]).pop_back() {
+            return Some(job);
+        }
+
+        {
+            let mut injector = lock(&self.injector);
+            if let Some(job) = injector.pop_front() {
+                let batch = (injector.len() / self.threads.load(SeqCst).max(1)).min(MAX_BATCH);
+                if batch > 0 {
+                    lock(&self.locals[slot]).extend(injector.drain(..batch));
+                }
+                return Some(job);
+            }
+        }
+
+        // a busy deque is skipped rather than waited for
+        let count = self.locals.len();
+        for victim in (1..count).map(|i| (slot + i) % count) {
+            let mut stolen = match self.locals[victim].try_lock() {
+                Ok(mut deque) if !deque.is_empty() => {
+                    let half = deque.len().div_ceil(2);
+                    deque.drain(..half).collect::<VecDeque<Job>>()
+                }
+                _ => continue,
+            };
+            let job = stolen.pop_front();
+            if !stolen.is_empty() {
+                lock(&self.locals[slot]).extend(stolen);
+            }
+            return job;
+        }
+        None
+    }
+
+    fn run_job(&self, id: usize, job: Job) {
+        self.queued.fetch_sub(1, SeqCst);
+        self.active.fetch_add(1, SeqCst);
+        if self.blocked.load(SeqCst) > 0 {
+            let _state = lock(&self.state);
+            self.space.notify_all();
+        }
+
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+            let hook = self
+                .panic_hook
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            // a hook that panics must not end the worker either
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(id, &panic_message(&payload))));
+        }
+        self.active.fetch_sub(1, SeqCst);
+    }
+}
+
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+}
+
+impl ThreadPool {
+    /// Create a new ThreadPool
+    ///
+    /// The size is the number of threads in the pool. A job that panics
+    /// does not take its thread down, so the pool keeps its size.
+    ///
+    /// # Panics
+    ///
+    /// The new function will panic if the size is zero.
+    pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::with_config(PoolConfig::new(size))
+    }
+
+    /// Create a ThreadPool of size threads with a bounded queue; see
+    /// PoolConfig::queue.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is zero.
+    pub fn bounded(size: usize, capacity: usize, policy: OverflowPolicy) -> ThreadPool {
+        ThreadPool::with_config(PoolConfig::new(size).queue(capacity, policy))
+    }
+
+    /// Create a ThreadPool and start its minimum number of threads
+    ///
+    /// # Panics
+    ///
+    /// Panics if the maximum is zero or below the minimum, or if a thread
+    /// can't be started.
+    pub fn with_config(config: PoolConfig) -> ThreadPool {
+        assert!(config.max_threads > 0);
+        assert!(config.min_threads <= config.max_threads);
+
+        let shared = Arc::new(Shared {
+            injector: Mutex::new(VecDeque::new()),
+            locals: (0..config.max_threads)
+                .map(|_| Mutex::new(VecDeque::new()))
+                .collect(),
+            state: Mutex::new(PoolState {
+                free_slots: (0..config.max_threads).rev().collect(),
+                ..PoolState::default()
+            }),
+            work: Condvar::new(),
+            space: Condvar::new(),
+            queued: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            threads: AtomicUsize::new(0),
+            sleeping: AtomicUsize::new(0),
+            waking: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+            panic_hook: RwLock::new(Box::new(|id, message| {
+                println!("Worker {id} job panicked: {message}");
+            })),
+            config,
+        });
+
+        {
+            let mut state = lock(&shared.state);
+            for _ in 0..shared.config.min_threads {
+                Worker::spawn(&shared, &mut state).expect("Failed to start a worker thread.");
+            }
+        }
+
+        ThreadPool { shared }
+    }
+
+    /// Report panicking jobs with this hook instead of printing them
+    pub fn with_panic_hook<F>(self, hook: F) -> ThreadPool
+    where
+        F: Fn(usize, &str) + Send + Sync + 'static,
+    {
+        *self
+            .shared
+            .panic_hook
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Box::new(hook);
+        self
+    }
+
+    /// the number of worker threads running now
+    pub fn size(&self) -> usize {
+        self.shared.threads.load(SeqCst)
+    }
+
+    /// Run a job on the pool
+    ///
+    /// # Panics
+    ///
+    /// Panics if the job is rejected; use try_execute to handle that.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Err(error) = self.try_execute(f) {
+            panic!("Cannot execute job: {}", error);
+        }
+    }
+
+    /// Run a job on the pool, or say why it can't be queued. A thread is
+    /// added when the job would otherwise wait. A bounded pool with a full
+    /// queue blocks, rejects or runs the job right here, as its policy says.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let shared = &self.shared;
+        let job: Job = Box::new(f);
+
+        match shared.config.queue {
+            None => {
+                shared.queued.fetch_add(1, SeqCst);
+            }
+            Some((capacity, policy)) => {
+                while !shared.try_reserve(capacity) {
+                    match policy {
+                        OverflowPolicy::Block => shared.wait_for_space(),
+                        OverflowPolicy::Reject => return Err(ExecuteError::Full),
+                        OverflowPolicy::CallerRuns => {
+                            job();
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+
+        shared.push(job);
+        shared.notify();
+        Ok(())
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        {
+            // workers finish the jobs already queued before they exit
+            let mut state = lock(&self.shared.state);
+            state.closed = true;
+            self.shared.work.notify_all();
+        }
+
+        let workers = std::mem::take(&mut lock(&self.shared.state).workers);
+        for mut worker in workers {
+            println!("Shutting down worker {}", worker.id);
+
+            if let Some(thread) = worker.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Start a thread with a free deque
+    fn spawn(shared: &Arc<Shared>, state: &mut PoolState) -> io::Result<()> {
+        let slot = state
+            .free_slots
+            .pop()
+            .ok_or_else(|| io::Error::other("every worker deque is in use"))?;
+        let id = state.next_id;
+        let mut builder = thread::Builder::new();
+        if let Some(prefix) = &shared.config.thread_name_prefix {
+            builder = builder.name(format!("{prefix}-{id}"));
+        }
+        if let Some(size) = shared.config.stack_size {
+            builder = builder.stack_size(size);
+        }
+
+        let worker_shared = Arc::clone(shared);
+        let thread = match builder.spawn(move || Worker::run(id, slot, &worker_shared)) {
+            Ok(thread) => thread,
+            Err(error) => {
+                state.free_slots.push(slot);
+                return Err(error);
+            }
+        };
+
+        // threads that shrank the pool have nothing left to join
+        state
+            .workers
+            .retain(|w| w.thread.as_ref().is_some_and(|t| !t.is_finished()));
+        state.workers.push(Worker {
+            id,
+            thread: Some(thread),
+        });
+        state.next_id += 1;
+        shared.threads.fetch_add(1, SeqCst);
+        Ok(())
+    }
+
+    fn run(id: usize, slot: usize, shared: &Arc<Shared>) {
+        CURRENT_WORKER.set(Some((shared.id(), slot)));
+
+        let mut woken = false;
+        loop {
+            if let Some(job) = shared.find_job(slot) {
+                if mem::take(&mut woken) && shared.queued.load(SeqCst) > 1 {
+                    shared.notify();
+                }
+                shared.run_job(id, job);
+                continue;
+            }
+
+            let state = lock(&shared.state);
+            if state.closed && shared.queued.load(SeqCst) == 0 {
+                println!("Worker {id} disconnected; shutting down.");
+                Worker::exit(slot, shared, state);
+                return;
+            }
+
+            // counted as sleeping before the last look, so a job pushed
+            // after it is sure to wake this worker
+            shared.sleeping.fetch_add(1, SeqCst);
+            if shared.queued.load(SeqCst) > 0 {
+                shared.sleeping.fetch_sub(1, SeqCst);
+                drop(state);
+                thread::yield_now();
+                continue;
+            }
+            let (state, wait) = shared
+                .work
+                .wait_timeout(state, shared.config.idle_timeout)
+                .unwrap_or_else(PoisonError::into_inner);
+            match shared.waking.load(SeqCst) {
+                0 => shared.sleeping.fetch_sub(1, SeqCst),
+                _ => {
+                    woken = true;
+                    shared.waking.fetch_sub(1, SeqCst)
+                }
+            };
+
+            if wait.timed_out()
+                && !state.closed
+                && shared.queued.load(SeqCst) == 0
+                && shared.threads.load(SeqCst) > shared.config.min_threads
+            {
+                println!("Worker {id} idle; shutting down.");
+                Worker::exit(slot, shared, state);
+                return;
+            }
+        }
+    }
+
+    /// Give back the deque, which is empty because only this worker adds
+    /// to it
+    fn exit(slot: usize, shared: &Shared, mut state: MutexGuard<'_, PoolState>) {
+        state.free_slots.push(slot);
+        shared.threads.fetch_sub(1, SeqCst);
+    }
+}
+
+/// the message a panic was started with
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc;
+
+    #[test]
+    fn workers_survive_panics() {
+        let panics = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&panics);
+        let pool = ThreadPool::new(2).with_panic_hook(move |_, message| {
+            assert_eq!("bad job 1", message);
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        for _ in 0..4 {
+            pool.execute(|| panic!("bad job {}", 1));
+        }
+        let (tx, rx) = mpsc::channel();
+        for n in 0..4 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(n).unwrap());
+        }
+
+        let mut done: Vec<i32> = (0..4)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        done.sort();
+        assert_eq!(vec![0, 1, 2, 3], done);
+
+        // jobs don't run in order, so the last panics may still be reported
+        let mut waited = Duration::ZERO;
+        while panics.load(Ordering::SeqCst) < 4 && waited < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+            waited += Duration::from_millis(10);
+        }
+        assert_eq!(4, panics.load(Ordering::SeqCst));
+        assert_eq!(2, pool.size());
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let gate = Arc::new(Mutex::new(gate_rx));
+
+        // keep the only thread busy and the one queue slot taken
+        let fill = |pool: &ThreadPool| {
+            let (started_tx, started_rx) = mpsc::channel();
+            for _ in 0..2 {
+                let gate = Arc::clone(&gate);
+                let started_tx = started_tx.clone();
+                pool.execute(move || {
+                    started_tx.send(()).unwrap();
+                    let _ = gate.lock().unwrap().recv();
+                });
+                // the first job has to leave the queue before the second
+                // takes its place
+                let _ = started_rx.recv_timeout(Duration::from_millis(200));
+            }
+        };
+
+        let rejecting = ThreadPool::bounded(1, 1, OverflowPolicy::Reject);
+        fill(&rejecting);
+        assert_eq!(Err(ExecuteError::Full), rejecting.try_execute(|| ()));
+
+        let caller_runs = ThreadPool::bounded(1, 1, OverflowPolicy::CallerRuns);
+        fill(&caller_runs);
+        let (ran_tx, ran_rx) = mpsc::channel();
+        caller_runs
+            .try_execute(move || ran_tx.send(thread::current().id()).unwrap())
+            .unwrap();
+        assert_eq!(thread::current().id(), ran_rx.recv().unwrap());
+
+        drop(gate_tx);
+    }
+
+    #[test]
+    fn elastic() {
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let gate = Arc::new(Mutex::new(gate_rx));
+        let pool = ThreadPool::with_config(
+            PoolConfig::elastic(1, 3)
+                .idle_timeout(Duration::from_millis(50))
+                .thread_name_prefix("elastic"),
+        );
+        assert_eq!(1, pool.size());
+
+        let (names_tx, names_rx) = mpsc::channel();
+        for _ in 0..5 {
+            let gate = Arc::clone(&gate);
+            let names_tx = names_tx.clone();
+            pool.execute(move || {
+                names_tx
+                    .send(thread::current().name().unwrap().to_string())
+                    .unwrap();
+                let _ = gate.lock().unwrap().recv();
+            });
+        }
+        for _ in 0..3 {
+            assert!(names_rx.recv().unwrap().starts_with("elastic-"));
+        }
+        assert_eq!(3, pool.size());
+
+        // the jobs end, then the extra threads time out
+        drop(gate_tx);
+        let mut waited = Duration::ZERO;
+        while pool.size() > 1 && waited < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(20));
+            waited += Duration::from_millis(20);
+        }
+        assert_eq!(1, pool.size());
+    }
+
+    #[test]
+    fn jobs_from_jobs() {
+        let pool = Arc::new(ThreadPool::new(4));
+        let done = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpsc::channel();
+
+        for _ in 0..10 {
+            let (inner, done, tx) = (Arc::clone(&pool), Arc::clone(&done), tx.clone());
+            pool.execute(move || {
+                for _ in 0..100 {
+                    let (done, tx) = (Arc::clone(&done), tx.clone());
+                    inner.execute(move || {
+                        if done.fetch_add(1, Ordering::SeqCst) + 1 == 1000 {
+                            tx.send(()).unwrap();
+                        }
+                    });
+                }
+            });
+        }
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // the pool must not be dropped by one of its own jobs
+        while Arc::strong_count(&pool) > 1 {
+            thread::yield_now();
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use crate::headers::Headers;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+/// longest request line or header line accepted, in bytes
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+
+/// most header fields accepted in one request
+const MAX_HEADERS: usize = 100;
+
+/// largest request body accepted, in bytes
+const MAX_BODY_LENGTH: usize = 10 * 1024 * 1024;
+
+/// Why a request could not be read
+#[derive(Debug)]
+pub enum ParseError {
+    /// the connection ended before a request started
+    Closed,
+    Io(io::Error),
+    /// the request is not valid HTTP; the message says what is wrong
+    Malformed(String),
+    /// a line, the header count or the body is over the limit
+    TooLarge(&'static str),
+    /// an HTTP version other than 1.0 and 1.1
+    UnsupportedVersion(String),
+    /// valid HTTP that this server cannot handle yet
+    NotImplemented(String),
+}
+
+impl ParseError {
+    /// the status code of the response that reports this error
+    pub fn status(&self) -> u16 {
+        match self {
+            ParseError::TooLarge(_) => 413,
+            ParseError::UnsupportedVersion(_) => 505,
+            ParseError::NotImplemented(_) => 501,
+            _ => 400,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Closed => write!(f, "connection closed"),
+            ParseError::Io(error) => write!(f, "read failed: {}", error),
+            ParseError::Malformed(message) => write!(f, "malformed request: {}", message),
+            ParseError::TooLarge(what) => write!(f, "{} too large", what),
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "unsupported HTTP version {}", version)
+            }
+            ParseError::NotImplemented(what) => write!(f, "{} not implemented", what),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(error: io::Error) -> ParseError {
+        ParseError::Io(error)
+    }
+}
+
+fn malformed(message: &str) -> ParseError {
+    ParseError::Malformed(message.into())
+}
+
+/// An HTTP request
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    method: String,
+    target: String,
+    path: String,
+    query: Vec<(String, String)>,
+    version: String,
+    headers: Headers,
+    body: Vec<u8>,
+    trailers: Headers,
+}
+
+impl Request {
+    /// Read one request from a stream: the request line, the headers and a
+    /// body of Content-Length bytes or in chunked transfer encoding.
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Request, ParseError> {
+        let request_line = match read_line(reader)? {
+            Some(line) => line,
+            None => return Err(ParseError::Closed),
+        };
+
+        let mut parts = request_line.split(' ');
+        let (method, target, version) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(method), Some(target), Some(version), None) => (method, target, version),
+                _ => return Err(malformed("expected 'METHOD target HTTP/version'")),
+            };
+        if method.is_empty() || !method.bytes().all(is_token_byte) {
+            return Err(malformed("invalid method"));
+        }
+        match version {
+            "HTTP/1.1" | "HTTP/1.0" => (),
+            v if v.starts_with("HTTP/") => return Err(ParseError::UnsupportedVersion(v.into())),
+            _ => return Err(malformed("invalid HTTP version")),
+        }
+
+        let (path, query) = parse_target(target)?;
+        let headers = read_headers(reader)?;
+        let (body, trailers) = read_body(reader, &headers)?;
+
+        Ok(Request {
+            method: method.into(),
+            target: target.into(),
+            path,
+            query,
+            version: version.into(),
+            headers,
+            body,
+            trailers,
+        })
+    }
+
+    /// the method, e.g. GET
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// the request target exactly as it was sent
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// the percent-decoded path, without the query string
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// the decoded query parameters, in order
+    pub fn query(&self) -> &[(String, String)] {
+        &self.query
+    }
+
+    /// the first value of a query parameter
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// the protocol version, e.g. HTTP/1.1
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// shortcut for headers().get(name)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// header fields sent after a chunked body
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+}
+
+/// Read a line ending in CRLF (or a bare LF) without the line ending.
+/// Returns None at the end of the stream.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let read = reader
+        .take(MAX_LINE_LENGTH as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return match line.len() > MAX_LINE_LENGTH {
+            true => Err(ParseError::TooLarge("line")),
+            false => Err(malformed("unexpected end of request")),
+        };
+    }
+
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| malformed("line is not valid UTF-8"))
+}
+
+fn read_headers<R: BufRead>(reader: &mut R) -> Result<Headers, ParseError> {
+    let mut headers = Headers::new();
+    loop {
+        let line = read_line(reader)?.ok_or_else(|| malformed("unexpected end of headers"))?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(ParseError::TooLarge("header section"));
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| malformed("header without ':'"))?;
+        if name.is_empty() || !name.bytes().all(is_token_byte) {
+            return Err(malformed("invalid header name"));
+        }
+        headers.append(name, value.trim());
+    }
+}
+
+fn read_body<R: BufRead>(
+    reader: &mut R,
+    headers: &Headers,
+) -> Result<(Vec<u8>, Headers), ParseError> {
+    if headers.contains("Transfer-Encoding") {
+        // a body framed two ways is a request smuggling attempt
+        if headers.contains("Content-Length") {
+            return Err(malformed("both Transfer-Encoding and Content-Length"));
+        }
+        let codings: Vec<String> = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|v| v.split(','))
+            .map(|c| c.trim().to_ascii_lowercase())
+            .filter(|c| !c.is_empty())
+            .collect();
+        return match codings.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["chunked"] => read_chunked_body(reader),
+            [.., "chunked"] => Err(ParseError::NotImplemented(format!(
+                "Transfer-Encoding {}",
+                codings.join(", ")
+            ))),
+            _ => Err(malformed("Transfer-Encoding must end with chunked")),
+        };
+    }
+
+    let mut lengths = headers.get_all("Content-Length");
+    let length = match lengths.next() {
+        Some(length) => length,
+        None => return Ok((Vec::new(), Headers::new())),
+    };
+    if lengths.any(|other| other != length) {
+        return Err(malformed("conflicting Content-Length headers"));
+    }
+    let length: usize = match length.bytes().all(|b| b.is_ascii_digit()) {
+        true => length.parse().map_err(|_| ParseError::TooLarge("body"))?,
+        false => return Err(malformed("invalid Content-Length")),
+    };
+    if length > MAX_BODY_LENGTH {
+        return Err(ParseError::TooLarge("body"));
+    }
+
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::UnexpectedEof => malformed("body shorter than Content-Length"),
+            _ => ParseError::Io(error),
+        })?;
+    Ok((body, Headers::new()))
+}
+
+/// Read chunks until the zero length chunk, then the trailer fields
+fn read_chunked_body<R: BufRead>(reader: &mut R) -> Result<(Vec<u8>, Headers), ParseError> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?.ok_or_else(|| malformed("unexpected end of chunked body"))?;
+        // chunk extensions after ';' are allowed and ignored
+        let size = line.split(';').next().unwrap_or("").trim();
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(malformed("invalid chunk size"));
+        }
+        let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::TooLarge("body"))?;
+        if size == 0 {
+            break;
+        }
+        // the body never exceeds the maximum, so this can't underflow
+        if size > MAX_BODY_LENGTH - body.len() {
+            return Err(ParseError::TooLarge("body"));
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader
+            .read_exact(&mut body[start..])
+            .map_err(|error| match error.kind() {
+                io::ErrorKind::UnexpectedEof => malformed("chunk shorter than its size"),
+                _ => ParseError::Io(error),
+            })?;
+        if read_line(reader)?.as_deref() != Some("") {
+            return Err(malformed("chunk longer than its size"));
+        }
+    }
+
+    let trailers = read_headers(reader)?;
+    Ok((body, trailers))
+}
+
+/// Split a request target into the decoded path and query parameters
+fn parse_target(target: &str) -> Result<(String, Vec<(String, String)>), ParseError> {
+    // absolute-form, as sent to proxies: http://host/path
+    let target = match target.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/').unwrap_or(rest.len())..],
+        None => target,
+    };
+    let target = target.split('#').next().unwrap_or("");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let path = match path {
+        "" => String::from("/"),
+        "*" => String::from("*"),
+        p if p.starts_with('/') => percent_decode(p, false)?,
+        _ => return Err(malformed("request target must start with '/'")),
+    };
+
+    let mut params = Vec::new();
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        params.push((percent_decode(name, true)?, percent_decode(value, true)?));
+    }
+    Ok((path, params))
+}
+
+/// Decode %XX escapes, and '+' as a space in query strings
+pub fn percent_decode(text: &str, plus_as_space: bool) -> Result<String, ParseError> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'%' => {
+                let hex = bytes
+                    .get(idx + 1..idx + 3)
+                    .and_then(|h| std::str::from_utf8(h).ok())
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| malformed("invalid percent escape"))?;
+                decoded.push(hex);
+                idx += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                idx += 1;
+            }
+            b => {
+                decoded.push(b);
+                idx += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| malformed("percent-decoded text is not valid UTF-8"))
+}
+
+/// characters allowed in methods and header names (RFC 9110 tchar)
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Request, ParseError> {
+        Request::read_from(&mut text.as_bytes())
+    }
+
+    #[test]
+    fn request() {
+        let request = parse(
+            "POST /users/J%C3%BCrgen?q=a+b&x=%26&flag HTTP/1.1\r\nHost: localhost\r\ncontent-length: 5\r\n\r\nhello",
+        )
+        .unwrap();
+
+        assert_eq!("POST", request.method());
+        assert_eq!("/users/Jürgen", request.path());
+        assert_eq!(Some("a b"), request.query_param("q"));
+        assert_eq!(Some("&"), request.query_param("x"));
+        assert_eq!(Some(""), request.query_param("flag"));
+        assert_eq!(Some("localhost"), request.header("HOST"));
+        assert_eq!(b"hello", request.body());
+
+        let request = parse("GET http://example.com/a%20b HTTP/1.0\n\n").unwrap();
+        assert_eq!(("/a b", "HTTP/1.0"), (request.path(), request.version()));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(parse(""), Err(ParseError::Closed)));
+        assert_eq!(400, parse("GET /\r\n\r\n").unwrap_err().status());
+        assert_eq!(
+            400,
+            parse("GET /%zz HTTP/1.1\r\n\r\n").unwrap_err().status()
+        );
+        assert_eq!(
+            400,
+            parse("GET / HTTP/1.1\r\nbad header\r\n\r\n")
+                .unwrap_err()
+                .status()
+        );
+        assert_eq!(
+            400,
+            parse("GET / HTTP/1.1\r\nContent-Length: 9\r\n\r\nshort")
+                .unwrap_err()
+                .status()
+        );
+        assert_eq!(505, parse("GET / HTTP/2.0\r\n\r\n").unwrap_err().status());
+    }
+
+    #[test]
+    fn chunked() {
+        let request = parse(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\nB\r\n, chunked!!\r\n0\r\nDigest: abc\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(b"hello, chunked!!", request.body());
+        assert_eq!(Some("abc"), request.trailers().get("digest"));
+
+        let chunked = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n";
+        assert_eq!(
+            400,
+            parse(&format!("{}Content-Length: 3\r\n\r\n0\r\n\r\n", chunked))
+                .unwrap_err()
+                .status()
+        );
+        assert_eq!(
+            400,
+            parse(&format!("{}\r\n3\r\nhello\r\n0\r\n\r\n", chunked))
+                .unwrap_err()
+                .status()
+        );
+        assert_eq!(
+            400,
+            parse(&format!("{}\r\nzz\r\n", chunked))
+                .unwrap_err()
+                .status()
+        );
+        assert_eq!(
+            413,
+            parse(&format!("{}\r\n1\r\na\r\nffffffffffffffff\r\n", chunked))
+                .unwrap_err()
+                .status()
+        );
+        assert_eq!(
+            501,
+            parse("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n")
+                .unwrap_err()
+                .status()
+        );
+    }
+}
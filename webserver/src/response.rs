@@ -0,0 +1,356 @@
+use crate::headers::Headers;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// streamed output is sent in chunks of about this many bytes
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// A function that produces a streamed body
+pub type StreamFn = dyn Fn(&mut BodyWriter) -> io::Result<()> + Send + Sync;
+
+/// The body of a response
+#[derive(Clone)]
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// a file that is opened and copied to the connection when the response
+    /// is written, so it never has to fit in memory
+    File(PathBuf),
+    /// output produced while the response is written, sent with chunked
+    /// transfer encoding
+    Stream(Arc<StreamFn>),
+}
+
+impl Body {
+    /// the body's contents, if they are held in memory
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            Body::File(path) => f.debug_tuple("File").field(path).finish(),
+            Body::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+/// streams are equal only if they are the same function
+impl PartialEq for Body {
+    fn eq(&self, other: &Body) -> bool {
+        match (self, other) {
+            (Body::Bytes(a), Body::Bytes(b)) => a == b,
+            (Body::File(a), Body::File(b)) => a == b,
+            (Body::Stream(a), Body::Stream(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Where a streamed body is written. Output is buffered and sent a chunk
+/// at a time; flush sends what has been written so far.
+pub struct BodyWriter<'a> {
+    writer: &'a mut dyn Write,
+    buffer: Vec<u8>,
+    trailers: Headers,
+    chunked: bool,
+}
+
+impl<'a> BodyWriter<'a> {
+    fn new(writer: &'a mut dyn Write, chunked: bool) -> BodyWriter<'a> {
+        BodyWriter {
+            writer,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            trailers: Headers::new(),
+            chunked,
+        }
+    }
+
+    /// Add a header field to send after the body. List its name in a
+    /// Trailer header so clients know to expect it.
+    pub fn trailer(&mut self, name: &str, value: &str) {
+        self.trailers.append(name, value);
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        if self.chunked {
+            write!(self.writer, "{:X}\r\n", self.buffer.len())?;
+            self.writer.write_all(&self.buffer)?;
+            self.writer.write_all(b"\r\n")?;
+        } else {
+            self.writer.write_all(&self.buffer)?;
+        }
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Send the rest of the output, the last chunk and the trailers
+    fn finish(mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        if self.chunked {
+            let mut end = String::from("0\r\n");
+            for (name, value) in self.trailers.iter() {
+                end.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            end.push_str("\r\n");
+            self.writer.write_all(end.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for BodyWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        self.writer.flush()
+    }
+}
+
+/// An HTTP response. Content-Length is filled in when it is written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    status: u16,
+    headers: Headers,
+    body: Body,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Bytes(Vec::new()),
+        }
+    }
+
+    /// a response with a plain text body
+    pub fn text(status: u16, text: &str) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(text)
+    }
+
+    /// a response with an HTML body
+    pub fn html(status: u16, html: &str) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(html)
+    }
+
+    /// Set a header, replacing earlier values
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn with_body<B: Into<Vec<u8>>>(mut self, body: B) -> Response {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    /// Produce the body while the response is written, e.g.
+    /// `with_stream(|body| writeln!(body, "line"))`
+    pub fn with_stream<F>(mut self, stream: F) -> Response
+    where
+        F: Fn(&mut BodyWriter) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.body = Body::Stream(Arc::new(stream));
+        self
+    }
+
+    /// Send the contents of a file as the body
+    pub fn with_file<P: AsRef<Path>>(mut self, path: P) -> Response {
+        self.body = Body::File(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    /// Run a streamed body now and keep its output, for clients that can't
+    /// take chunked transfer encoding. Trailers are dropped.
+    pub fn into_buffered(mut self) -> io::Result<Response> {
+        if let Body::Stream(stream) = &self.body {
+            let mut bytes = Vec::new();
+            let mut body = BodyWriter::new(&mut bytes, false);
+            stream(&mut body)?;
+            body.finish()?;
+            self.body = Body::Bytes(bytes);
+        }
+        Ok(self)
+    }
+
+    /// Write the status line, headers and body. A file body is opened here,
+    /// and an error opening it is returned before anything is written.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.write(writer, true)
+    }
+
+    /// Write the status line and headers only, as the answer to a HEAD
+    /// request. The framing headers are the ones the body would get, but a
+    /// streamed body is not run.
+    pub fn write_head_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.write(writer, false)
+    }
+
+    fn write<W: Write>(&self, writer: &mut W, with_body: bool) -> io::Result<()> {
+        let mut file = None;
+        let framing = match &self.body {
+            Body::Bytes(bytes) => format!("Content-Length: {}", bytes.len()),
+            Body::File(path) => {
+                let opened = File::open(path)?;
+                let length = opened.metadata()?.len();
+                file = Some((opened.take(length), length));
+                format!("Content-Length: {}", length)
+            }
+            Body::Stream(_) => String::from("Transfer-Encoding: chunked"),
+        };
+
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason_phrase(self.status)
+        );
+        for (name, value) in self.headers.iter().filter(|(n, _)| !is_framing_header(n)) {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("{}\r\n\r\n", framing));
+        writer.write_all(head.as_bytes())?;
+        if !with_body {
+            return writer.flush();
+        }
+
+        match &self.body {
+            Body::Bytes(bytes) => writer.write_all(bytes)?,
+            Body::Stream(stream) => {
+                let mut body = BodyWriter::new(writer, true);
+                stream(&mut body)?;
+                body.finish()?;
+            }
+            Body::File(_) => (),
+        }
+        // the file may have shrunk since its length was sent
+        if let Some((file, length)) = file.as_mut() {
+            if io::copy(file, writer)? < *length {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "file shorter than its length",
+                ));
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// headers that write_to sets from the body
+fn is_framing_header(name: &str) -> bool {
+    name.eq_ignore_ascii_case("Content-Length") || name.eq_ignore_ascii_case("Transfer-Encoding")
+}
+
+/// the standard reason phrase for a status code
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streaming() {
+        let response = Response::new(200)
+            .with_header("Trailer", "Rows")
+            .with_stream(|body| {
+                body.write_all(b"first,")?;
+                body.flush()?;
+                body.write_all(b"second")?;
+                body.trailer("Rows", "2");
+                Ok(())
+            });
+
+        let mut output = Vec::new();
+        response.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(
+            "Transfer-Encoding: chunked\r\n\r\n6\r\nfirst,\r\n6\r\nsecond\r\n0\r\nRows: 2\r\n\r\n"
+        ));
+        assert!(!output.contains("Content-Length"));
+
+        let buffered = response.into_buffered().unwrap();
+        assert_eq!(Some(&b"first,second"[..]), buffered.body().as_bytes());
+    }
+
+    #[test]
+    fn head_only() {
+        let mut output = Vec::new();
+        Response::text(200, "hello")
+            .write_head_to(&mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with("Content-Length: 5\r\n\r\n"));
+
+        let mut output = Vec::new();
+        Response::new(200)
+            .with_stream(|_| panic!("a HEAD response runs no stream"))
+            .write_head_to(&mut output)
+            .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .ends_with("Transfer-Encoding: chunked\r\n\r\n"));
+    }
+}
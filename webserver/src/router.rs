@@ -0,0 +1,290 @@
+use crate::request::Request;
+use crate::response::Response;
+
+/// A request handler. It gets the request and the parameters taken from the
+/// path by the route's pattern.
+pub type Handler = Box<dyn Fn(&Request, &Params) -> Response + Send + Sync>;
+
+/// Path parameters extracted by a route pattern
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+impl Params {
+    /// the value of a `:name` or `*name` segment
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    /// the rest of the path, possibly empty
+    Wildcard(String),
+}
+
+struct Route {
+    method: String,
+    pattern: Vec<Segment>,
+    handler: Handler,
+}
+
+/// Dispatches requests to handlers by method and path pattern.
+///
+/// Patterns are paths whose segments can be literals, `:name` to match any
+/// one segment, or a final `*name` (or just `*`) to match the rest of the
+/// path. Routes are tried in the order they were added.
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Handler,
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_, _| Response::text(404, "Not Found\n")),
+        }
+    }
+
+    /// Add a route for a method and path pattern
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern does not start with '/', has a parameter
+    /// without a name or a wildcard that is not the last segment.
+    pub fn route<F>(&mut self, method: &str, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method: method.to_ascii_uppercase(),
+            pattern: parse_pattern(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<F>(&mut self, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route("GET", pattern, handler)
+    }
+
+    pub fn post<F>(&mut self, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route("POST", pattern, handler)
+    }
+
+    pub fn put<F>(&mut self, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route("PUT", pattern, handler)
+    }
+
+    pub fn delete<F>(&mut self, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route("DELETE", pattern, handler)
+    }
+
+    /// Replace the handler for requests that match no route
+    pub fn not_found<F>(&mut self, handler: F) -> &mut Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    /// Run the handler of the first route matching the request. A HEAD
+    /// request without a HEAD route runs the GET route of the path instead;
+    /// the connection sends only the head of its response. A path that
+    /// matches only routes for other methods gets a 405 listing them in the
+    /// Allow header.
+    pub fn handle(&self, request: &Request) -> Response {
+        let mut allowed: Vec<&str> = Vec::new();
+        let mut get_route = None;
+
+        for route in self.routes.iter() {
+            let params = match match_path(&route.pattern, request.path()) {
+                Some(params) => params,
+                None => continue,
+            };
+            if route.method == request.method() {
+                return (route.handler)(request, &params);
+            }
+            if route.method == "GET" && get_route.is_none() {
+                get_route = Some((route, params));
+            }
+            if !allowed.contains(&route.method.as_str()) {
+                allowed.push(&route.method);
+            }
+        }
+
+        if let Some((route, params)) = get_route {
+            if request.method() == "HEAD" {
+                return (route.handler)(request, &params);
+            }
+            if !allowed.contains(&"HEAD") {
+                allowed.push("HEAD");
+            }
+        }
+        if allowed.is_empty() {
+            return (self.not_found)(request, &Params::default());
+        }
+        Response::text(405, "Method Not Allowed\n").with_header("Allow", &allowed.join(", "))
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    assert!(
+        pattern.starts_with('/'),
+        "route pattern {} must start with '/'",
+        pattern
+    );
+
+    let parts: Vec<&str> = pattern[1..].split('/').filter(|p| !p.is_empty()).collect();
+    let mut segments = Vec::with_capacity(parts.len());
+    for (idx, part) in parts.iter().enumerate() {
+        let segment = if let Some(name) = part.strip_prefix(':') {
+            assert!(
+                !name.is_empty(),
+                "route pattern {} has a parameter without a name",
+                pattern
+            );
+            Segment::Param(name.into())
+        } else if let Some(name) = part.strip_prefix('*') {
+            assert!(
+                idx + 1 == parts.len(),
+                "wildcard in route pattern {} must come last",
+                pattern
+            );
+            Segment::Wildcard(name.into())
+        } else {
+            Segment::Literal(part.to_string())
+        };
+        segments.push(segment);
+    }
+    segments
+}
+
+fn match_path(pattern: &[Segment], path: &str) -> Option<Params> {
+    let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+    let mut params = Params::default();
+
+    for (idx, segment) in pattern.iter().enumerate() {
+        match segment {
+            Segment::Wildcard(name) => {
+                if !name.is_empty() {
+                    params
+                        .values
+                        .push((name.clone(), parts[idx.min(parts.len())..].join("/")));
+                }
+                return Some(params);
+            }
+            Segment::Literal(literal) => {
+                if parts.get(idx) != Some(&literal.as_str()) {
+                    return None;
+                }
+            }
+            Segment::Param(name) => params
+                .values
+                .push((name.clone(), parts.get(idx)?.to_string())),
+        }
+    }
+
+    match parts.len() == pattern.len() {
+        true => Some(params),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str) -> Request {
+        let text = format!("{} {} HTTP/1.1\r\n\r\n", method, path);
+        Request::read_from(&mut text.as_bytes()).unwrap()
+    }
+
+    fn body(response: &Response) -> String {
+        String::from_utf8(response.body().as_bytes().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn routing() {
+        let mut router = Router::new();
+        router
+            .get("/users/:id", |_, params| {
+                Response::text(200, params.get("id").unwrap())
+            })
+            .delete("/users/:id", |_, _| Response::new(204))
+            .get("/files/*rest", |_, params| {
+                Response::text(200, params.get("rest").unwrap())
+            })
+            .get("/", |_, _| Response::text(200, "home"));
+
+        assert_eq!("42", body(&router.handle(&request("GET", "/users/42"))));
+        assert_eq!(204, router.handle(&request("DELETE", "/users/42")).status());
+        assert_eq!(
+            "a/b.txt",
+            body(&router.handle(&request("GET", "/files/a/b.txt")))
+        );
+        assert_eq!("", body(&router.handle(&request("GET", "/files"))));
+        assert_eq!("home", body(&router.handle(&request("GET", "/"))));
+        assert_eq!(
+            404,
+            router.handle(&request("GET", "/users/42/posts")).status()
+        );
+
+        let response = router.handle(&request("POST", "/users/42"));
+        assert_eq!(405, response.status());
+        assert_eq!(Some("GET, DELETE, HEAD"), response.headers().get("allow"));
+    }
+
+    #[test]
+    fn head_falls_back_to_get() {
+        let mut router = Router::new();
+        router
+            .get("/users/:id", |_, params| {
+                Response::text(200, params.get("id").unwrap())
+            })
+            .get("/status", |_, _| Response::text(200, "up"))
+            .route("HEAD", "/status", |_, _| Response::new(204));
+
+        let response = router.handle(&request("HEAD", "/users/42"));
+        assert_eq!(200, response.status());
+        assert_eq!("42", body(&response));
+        assert_eq!(204, router.handle(&request("HEAD", "/status")).status());
+        assert_eq!(404, router.handle(&request("HEAD", "/missing")).status());
+    }
+
+    #[test]
+    #[should_panic]
+    fn wildcard_must_be_last() {
+        Router::new().get("/a/*rest/b", |_, _| Response::new(200));
+    }
+}
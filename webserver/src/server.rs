@@ -0,0 +1,307 @@
+use crate::connection::{self, ConnectionLimits};
+use crate::response::Response;
+use crate::router::Router;
+use crate::shutdown::ShutdownHandle;
+use crate::{ExecuteError, OverflowPolicy, PoolConfig, ThreadPool};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// default number of worker threads
+const DEFAULT_THREADS: usize = 4;
+
+/// default number of connections waiting for a free thread
+const DEFAULT_QUEUE_CAPACITY: usize = 64;
+
+/// longest the accept loop waits to send a 503 to a client
+const REJECT_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// default time in-flight requests get to finish after a shutdown request
+const DEFAULT_SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+
+/// The connections being served, so a shutdown can wait for them or cut
+/// them off
+#[derive(Default)]
+struct Connections {
+    streams: Mutex<(usize, HashMap<usize, TcpStream>)>,
+    closed: Condvar,
+}
+
+impl Connections {
+    fn add(&self, stream: TcpStream) -> usize {
+        let mut streams = self.streams.lock().unwrap();
+        let id = streams.0;
+        streams.0 += 1;
+        streams.1.insert(id, stream);
+        id
+    }
+
+    fn remove(&self, id: usize) {
+        self.streams.lock().unwrap().1.remove(&id);
+        self.closed.notify_all();
+    }
+
+    fn shutdown_all(&self, how: Shutdown) {
+        for stream in self.streams.lock().unwrap().1.values() {
+            let _ = stream.shutdown(how);
+        }
+    }
+
+    /// Wait until every connection is closed or the deadline passes.
+    /// Returns how many are still open.
+    fn wait_closed(&self, deadline: Instant) -> usize {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let streams = self.streams.lock().unwrap();
+        let (streams, _) = self
+            .closed
+            .wait_timeout_while(streams, timeout, |s| !s.1.is_empty())
+            .unwrap();
+        streams.1.len()
+    }
+}
+
+/// removes a connection when its job ends, even by a panic
+struct Registration(Arc<Connections>, usize);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.0.remove(self.1);
+    }
+}
+
+/// Accepts connections and answers their requests with a Router on a
+/// ThreadPool, until it is shut down through its ShutdownHandle.
+pub struct Server {
+    listener: TcpListener,
+    router: Arc<Router>,
+    pool: PoolConfig,
+    queue_capacity: usize,
+    limits: ConnectionLimits,
+    deadline: Duration,
+    shutdown: ShutdownHandle,
+}
+
+impl Server {
+    /// Listen on an address. Port 0 picks a free port; see local_addr.
+    pub fn bind<A: ToSocketAddrs>(addr: A, router: Router) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        let shutdown = ShutdownHandle::new();
+        shutdown.set_wake_addr(listener.local_addr()?);
+
+        Ok(Server {
+            listener,
+            router: Arc::new(router),
+            pool: PoolConfig::new(DEFAULT_THREADS),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            limits: ConnectionLimits::default(),
+            deadline: DEFAULT_SHUTDOWN_DEADLINE,
+            shutdown,
+        })
+    }
+
+    /// number of connections served at the same time
+    pub fn threads(self, threads: usize) -> Server {
+        self.pool(PoolConfig::new(threads))
+    }
+
+    /// Size and name the worker threads with a pool configuration. Its
+    /// queue settings are replaced by queue_capacity.
+    pub fn pool(mut self, config: PoolConfig) -> Server {
+        self.pool = config;
+        self
+    }
+
+    /// connections that may wait for a free thread; any more are answered
+    /// with 503 Service Unavailable
+    pub fn queue_capacity(mut self, capacity: usize) -> Server {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    pub fn limits(mut self, limits: ConnectionLimits) -> Server {
+        self.limits = limits;
+        self
+    }
+
+    /// how long in-flight requests may take to finish once shutdown starts;
+    /// connections still open after it are closed
+    pub fn shutdown_deadline(mut self, deadline: Duration) -> Server {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Use a handle created before the server, e.g. one a route holds
+    pub fn with_shutdown(self, shutdown: ShutdownHandle) -> io::Result<Server> {
+        shutdown.set_wake_addr(self.listener.local_addr()?);
+        Ok(Server { shutdown, ..self })
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serve until shutdown is requested, then stop accepting, give open
+    /// connections until the deadline to finish and join the workers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool configuration is invalid.
+    pub fn run(self) -> io::Result<()> {
+        let config = self
+            .pool
+            .clone()
+            .queue(self.queue_capacity, OverflowPolicy::Reject);
+        let pool = ThreadPool::with_config(config);
+        let connections = Arc::new(Connections::default());
+
+        for stream in self.listener.incoming() {
+            if self.shutdown.is_shutdown() {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    println!("Failed to accept a connection: {}", error);
+                    continue;
+                }
+            };
+
+            // cloning fails e.g. when out of file descriptors; only this
+            // connection is dropped, which closes it
+            let (registered, overflow) = match (stream.try_clone(), stream.try_clone()) {
+                (Ok(registered), Ok(overflow)) => (registered, overflow),
+                (Err(error), _) | (_, Err(error)) => {
+                    println!("Cannot serve a connection: {}", error);
+                    continue;
+                }
+            };
+
+            let registration = Registration(Arc::clone(&connections), connections.add(registered));
+            let router = Arc::clone(&self.router);
+            let limits = self.limits;
+            let shutdown = self.shutdown.clone();
+            let queued = pool.try_execute(move || {
+                let _registration = registration;
+                if let Err(error) = connection::serve_stream(&stream, &router, &limits, &shutdown) {
+                    println!("Connection failed: {}", error);
+                }
+            });
+            match queued {
+                Ok(()) => (),
+                Err(ExecuteError::Full) => reject(overflow),
+            }
+        }
+
+        println!("Shutting down.");
+        drop(self.listener);
+        let deadline = Instant::now() + self.deadline;
+
+        // idle connections see the end of the stream; a request being
+        // handled can still send its response
+        connections.shutdown_all(Shutdown::Read);
+        let open = connections.wait_closed(deadline);
+        if open > 0 {
+            println!(
+                "Closing {} connections still open after the deadline.",
+                open
+            );
+            connections.shutdown_all(Shutdown::Both);
+        }
+
+        drop(pool);
+        Ok(())
+    }
+}
+
+/// Tell a client the server is too busy, without waiting for its request
+fn reject(mut stream: TcpStream) {
+    let _ = stream.set_write_timeout(Some(REJECT_WRITE_TIMEOUT));
+    let _ = Response::text(503, "Service Unavailable\n")
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close")
+        .write_to(&mut stream);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::Response;
+    use std::io::{Read, Write};
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// everything the server sends until it closes the connection
+    fn read_to_close(client: &mut TcpStream) -> String {
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        received
+    }
+
+    #[test]
+    fn shutdown() {
+        let mut router = Router::new();
+        router.get("/", |_, _| Response::text(200, "hello"));
+        let server = Server::bind("127.0.0.1:0", router).unwrap().threads(2);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        // a kept-alive connection must not hold the server open
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = [0; 256];
+        let mut received = String::new();
+        while !received.ends_with("hello") {
+            let read = client.read(&mut response).unwrap();
+            assert!(read > 0, "connection closed after {:?}", received);
+            received.push_str(&String::from_utf8_lossy(&response[..read]));
+        }
+
+        handle.shutdown();
+        running.join().unwrap().unwrap();
+        assert_eq!(0, client.read(&mut response).unwrap());
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn full_queue() {
+        let (started, wait_started) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+        let wait_release = Mutex::new(wait_release);
+        let mut router = Router::new();
+        router.get("/block", move |_, _| {
+            started.send(()).unwrap();
+            let _ = wait_release.lock().unwrap().recv();
+            Response::text(200, "done")
+        });
+        let server = Server::bind("127.0.0.1:0", router)
+            .unwrap()
+            .threads(1)
+            .queue_capacity(0);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        // the only thread is busy and nothing may wait for it
+        let mut busy = TcpStream::connect(addr).unwrap();
+        busy.write_all(b"GET /block HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        wait_started.recv().unwrap();
+
+        let mut rejected = TcpStream::connect(addr).unwrap();
+        let received = read_to_close(&mut rejected);
+        assert!(received.starts_with("HTTP/1.1 503 "), "{:?}", received);
+        assert!(received.contains("Retry-After: 1\r\n"), "{:?}", received);
+
+        release.send(()).unwrap();
+        assert!(read_to_close(&mut busy).ends_with("done"));
+        handle.shutdown();
+        running.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+//! Asking a running Server to stop, from another thread, a handler or a
+//! signal.
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+#[cfg(unix)]
+use std::thread;
+use std::time::Duration;
+
+/// how often the signal watcher thread checks for a signal
+#[cfg(unix)]
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+struct State {
+    requested: AtomicBool,
+    /// the address of the listener to wake from accept()
+    wake: Mutex<Option<SocketAddr>>,
+}
+
+/// A cloneable handle that tells a Server to shut down
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownHandle {
+    state: Arc<State>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> ShutdownHandle {
+        ShutdownHandle::default()
+    }
+
+    /// Ask the server to stop. It stops accepting connections at once and
+    /// closes kept-alive connections after their current request.
+    pub fn shutdown(&self) {
+        if self.state.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        // connect to the listener so a blocked accept() sees the request
+        let wake = *self.state.wake.lock().unwrap();
+        if let Some(mut addr) = wake {
+            if addr.ip().is_unspecified() {
+                addr.set_ip(match addr {
+                    SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                    SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+                });
+            }
+            let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+        }
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_wake_addr(&self, addr: SocketAddr) {
+        *self.state.wake.lock().unwrap() = Some(addr);
+    }
+}
+
+#[cfg(unix)]
+mod signal {
+    use std::os::raw::c_int;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    pub const SIGINT: c_int = 2;
+    pub const SIGTERM: c_int = 15;
+
+    pub static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    /// only sets a flag, which is all a signal handler may safely do
+    extern "C" fn on_signal(_: c_int) {
+        RECEIVED.store(true, Ordering::SeqCst);
+    }
+
+    /// SIG_ERR is -1
+    pub fn install(signum: c_int) -> bool {
+        // SAFETY: on_signal matches the handler type signal expects, and it
+        // is async-signal-safe because all it does is store to an atomic
+        unsafe { signal(signum, on_signal) != usize::MAX }
+    }
+}
+
+/// Shut down through handle on SIGINT or SIGTERM. A watcher thread
+/// checks for the signals, so the handle can do what a signal handler
+/// can't. Only supported on Unix.
+pub fn shutdown_on_signals(handle: ShutdownHandle) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        if !signal::install(signal::SIGINT) || !signal::install(signal::SIGTERM) {
+            return Err(io::Error::last_os_error());
+        }
+        thread::Builder::new()
+            .name("signal-watcher".into())
+            .spawn(move || {
+                while !signal::RECEIVED.load(Ordering::SeqCst) {
+                    if handle.is_shutdown() {
+                        return;
+                    }
+                    thread::sleep(SIGNAL_POLL_INTERVAL);
+                }
+                println!("Received a signal, shutting down.");
+                handle.shutdown();
+            })?;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = handle;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "signals are only supported on Unix",
+        ))
+    }
+}